@@ -6,29 +6,32 @@ set -e
 echo "Building for Raspberry Pi..."
 cross build --release --target aarch64-unknown-linux-gnu
 
-# Check if Discord credentials are already set
-if [ -z "$DISCORD_TOKEN" ]; then
-    echo "Please enter your Discord bot token:"
-    read -s DISCORD_TOKEN
+# Check if the Discord webhook is already set
+if [ -z "$NOISEBELL_ENDPOINT_DISCORD_URL" ]; then
+    echo "Please enter your Discord webhook URL (leave empty to disable Discord):"
+    read -s NOISEBELL_ENDPOINT_DISCORD_URL
 fi
 
-if [ -z "$DISCORD_CHANNEL_ID" ]; then
-    echo "Please enter your Discord channel ID:"
-    read -s DISCORD_CHANNEL_ID
+# Discord is an endpoint next to the default one, whose URL comes from the config on the Pi
+if [ -n "$NOISEBELL_ENDPOINT_DISCORD_URL" ]; then
+    DISCORD_ENVIRONMENT="Environment=NOISEBELL_ENDPOINTS=default,discord
+Environment=NOISEBELL_ENDPOINT_DISCORD_KIND=discord
+Environment=NOISEBELL_ENDPOINT_DISCORD_URL=${NOISEBELL_ENDPOINT_DISCORD_URL}"
+else
+    DISCORD_ENVIRONMENT=""
 fi
 
 # Create service file with credentials
 cat > noisebell.service << EOL
 [Unit]
-Description=Noisebell Discord Notification Service
+Description=Noisebell Status Notification Service
 After=network.target
 
 [Service]
 Type=simple
 User=noisebridge
 WorkingDirectory=/home/noisebridge
-Environment=DISCORD_TOKEN=${DISCORD_TOKEN}
-Environment=DISCORD_CHANNEL_ID=${DISCORD_CHANNEL_ID}
+${DISCORD_ENVIRONMENT}
 ExecStart=/home/noisebridge/noisebell
 Restart=on-failure
 RestartSec=10
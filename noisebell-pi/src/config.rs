@@ -1,161 +1,896 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dotenvy::dotenv;
 use tracing::info;
 
+/// Config file read when no `--config` argument is given. It is optional.
+pub const DEFAULT_CONFIG_PATH: &str = "noisebell.toml";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub gpio: GpioConfig,
+    pub gpiod: GpiodConfig,
+    pub debounce: DebounceConfig,
+    pub inputs: Vec<InputConfig>,
     pub web_monitor: WebMonitorConfig,
     pub logging: LoggingConfig,
     pub monitor: MonitorConfig,
-    pub endpoint: EndpointConfig,
+    pub endpoints: Vec<EndpointConfig>,
+    pub outbox: OutboxConfig,
+    pub state: StateConfig,
+    pub history: HistoryConfig,
+    pub spaceapi: SpaceApiConfig,
+    pub mqtt: MqttConfig,
+    pub home_assistant: HomeAssistantConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gpio: GpioConfig::default(),
+            gpiod: GpiodConfig::default(),
+            debounce: DebounceConfig::default(),
+            inputs: Vec::new(),
+            web_monitor: WebMonitorConfig::default(),
+            logging: LoggingConfig::default(),
+            monitor: MonitorConfig::default(),
+            endpoints: vec![EndpointConfig {
+                name: "default".to_string(),
+                url: "https://noisebell.jetpham.com/api/status".to_string(),
+                ..EndpointConfig::default()
+            }],
+            outbox: OutboxConfig::default(),
+            state: StateConfig::default(),
+            history: HistoryConfig::default(),
+            spaceapi: SpaceApiConfig::default(),
+            mqtt: MqttConfig::default(),
+            home_assistant: HomeAssistantConfig::default(),
+        }
+    }
+}
+
+/// Overrides `target` with the value of the environment variable `key`, if it is set.
+fn env_override<T: FromStr>(key: &str, target: &mut T) -> Result<()> {
+    if let Ok(value) = std::env::var(key) {
+        *target = value
+            .parse::<T>()
+            .map_err(|_| anyhow::anyhow!("Invalid value for {}: {}", key, value))?;
+    }
+    Ok(())
+}
+
+/// Like [`env_override`], for settings that are unset by default.
+fn env_override_option<T: FromStr>(key: &str, target: &mut Option<T>) -> Result<()> {
+    if let Ok(value) = std::env::var(key) {
+        *target = Some(
+            value
+                .parse::<T>()
+                .map_err(|_| anyhow::anyhow!("Invalid value for {}: {}", key, value))?,
+        );
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GpioConfig {
     pub pin: u8,
     pub debounce_delay_secs: u64,
 }
 
+impl Default for GpioConfig {
+    fn default() -> Self {
+        Self {
+            pin: 17,
+            debounce_delay_secs: 5,
+        }
+    }
+}
+
 impl GpioConfig {
-    pub fn from_env() -> Result<Self> {
-        let pin = std::env::var("NOISEBELL_GPIO_PIN")
-            .unwrap_or_else(|_| "17".to_string())
-            .parse::<u8>()
-            .map_err(|_| anyhow::anyhow!("Invalid GPIO pin number"))?;
-            
-        let debounce_delay_secs = std::env::var("NOISEBELL_GPIO_DEBOUNCE_DELAY_SECS")
-            .unwrap_or_else(|_| "5".to_string())
-            .parse::<u64>()
-            .map_err(|_| anyhow::anyhow!("Invalid debounce delay"))?;
-            
-        Ok(Self {
-            pin,
-            debounce_delay_secs,
-        })
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_GPIO_PIN", &mut self.pin)?;
+        env_override("NOISEBELL_GPIO_DEBOUNCE_DELAY_SECS", &mut self.debounce_delay_secs)?;
+        Ok(())
+    }
+}
+
+/// Input for the `gpiod` monitor, which uses the Linux GPIO character device instead of the
+/// Raspberry Pi registers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpiodConfig {
+    pub chip: String,
+    /// Offset of the line on `chip`, or a line name such as `GPIO17` to look up on every chip.
+    pub line: String,
+    /// `pull_up`, `pull_down`, `disabled` or `as_is` to keep the current setting.
+    pub bias: String,
+}
+
+impl Default for GpiodConfig {
+    fn default() -> Self {
+        Self {
+            chip: "/dev/gpiochip0".to_string(),
+            line: "17".to_string(),
+            bias: "pull_up".to_string(),
+        }
+    }
+}
+
+impl GpiodConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_GPIOD_CHIP", &mut self.chip)?;
+        env_override("NOISEBELL_GPIOD_LINE", &mut self.line)?;
+        env_override("NOISEBELL_GPIOD_BIAS", &mut self.bias)?;
+        Ok(())
+    }
+}
+
+/// Filtering applied to the state changes of an input before they are combined and published.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebounceConfig {
+    /// How long the input must stay open before the change counts. Defaults to
+    /// `gpio.debounce_delay_secs` for the gpio and gpiod monitors, and 0 for the web monitor.
+    pub open_settle_ms: Option<u64>,
+    pub close_settle_ms: Option<u64>,
+    /// How long a settled state must last before it is announced.
+    pub min_open_secs: u64,
+    pub min_closed_secs: u64,
+    /// Changes allowed within `flap_window_secs` before the state is held until the input
+    /// settles. 0 turns flap suppression off.
+    pub flap_max_changes: u32,
+    pub flap_window_secs: u64,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self {
+            open_settle_ms: None,
+            close_settle_ms: None,
+            min_open_secs: 0,
+            min_closed_secs: 0,
+            flap_max_changes: 0,
+            flap_window_secs: 600,
+        }
+    }
+}
+
+impl DebounceConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        self.apply_env_with_prefix("NOISEBELL_DEBOUNCE")
+    }
+
+    fn apply_env_with_prefix(&mut self, prefix: &str) -> Result<()> {
+        env_override_option(&format!("{}_OPEN_SETTLE_MS", prefix), &mut self.open_settle_ms)?;
+        env_override_option(&format!("{}_CLOSE_SETTLE_MS", prefix), &mut self.close_settle_ms)?;
+        env_override(&format!("{}_MIN_OPEN_SECS", prefix), &mut self.min_open_secs)?;
+        env_override(&format!("{}_MIN_CLOSED_SECS", prefix), &mut self.min_closed_secs)?;
+        env_override(&format!("{}_FLAP_MAX_CHANGES", prefix), &mut self.flap_max_changes)?;
+        env_override(&format!("{}_FLAP_WINDOW_SECS", prefix), &mut self.flap_window_secs)?;
+        Ok(())
+    }
+
+    /// Fills in the settle times for an input of `monitor_type`. Hardware inputs settle for
+    /// `default_settle_secs` unless configured.
+    pub fn settings(&self, monitor_type: &str, default_settle_secs: u64) -> crate::debounce::Settings {
+        // The web page has no bounces, and a delay would only make the switch feel broken
+        let default_settle_ms = match monitor_type {
+            "web" => 0,
+            _ => default_settle_secs * 1000,
+        };
+
+        crate::debounce::Settings {
+            open_settle: Duration::from_millis(self.open_settle_ms.unwrap_or(default_settle_ms)),
+            close_settle: Duration::from_millis(self.close_settle_ms.unwrap_or(default_settle_ms)),
+            min_open: Duration::from_secs(self.min_open_secs),
+            min_closed: Duration::from_secs(self.min_closed_secs),
+            flap_max_changes: self.flap_max_changes,
+            flap_window: Duration::from_secs(self.flap_window_secs),
+        }
     }
 }
 
+/// One of several inputs combined into the published state. Without any `[[inputs]]`, the
+/// single input is described by `[monitor]`, `[gpio]`, `[gpiod]` and `[debounce]`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub name: String,
+    /// `gpio`, `gpiod` or `web`.
+    pub monitor_type: String,
+    /// Pin for `gpio`.
+    pub pin: u8,
+    /// Chip, line and bias for `gpiod`, as in `[gpiod]`.
+    pub chip: String,
+    pub line: String,
+    pub bias: String,
+    /// `normal` when the input is low while the space is open, `inverted` when it is high.
+    pub polarity: String,
+    /// Decides between inputs for the `priority` rule. Higher wins.
+    pub priority: u32,
+    pub debounce: DebounceConfig,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let gpiod = GpiodConfig::default();
+        Self {
+            name: String::new(),
+            monitor_type: "gpio".to_string(),
+            pin: GpioConfig::default().pin,
+            chip: gpiod.chip,
+            line: gpiod.line,
+            bias: gpiod.bias,
+            polarity: "normal".to_string(),
+            priority: 0,
+            debounce: DebounceConfig::default(),
+        }
+    }
+}
+
+impl InputConfig {
+    /// Applies environment overrides to the list of inputs.
+    ///
+    /// `NOISEBELL_INPUTS` is a comma-separated list of input names that replaces the configured
+    /// list, keeping the file settings of inputs that appear in both. Each input is then
+    /// configured with `NOISEBELL_INPUT_<NAME>_*` variables.
+    pub fn apply_env_to_list(inputs: &mut Vec<Self>) -> Result<()> {
+        if let Ok(names) = std::env::var("NOISEBELL_INPUTS") {
+            let mut configured = std::mem::take(inputs);
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                let input = match configured.iter().position(|input| input.name == name) {
+                    Some(index) => configured.remove(index),
+                    None => Self {
+                        name: name.to_string(),
+                        ..Self::default()
+                    },
+                };
+                inputs.push(input);
+            }
+        }
+
+        for input in inputs.iter_mut() {
+            let prefix = format!("NOISEBELL_INPUT_{}", input.name.to_uppercase().replace('-', "_"));
+            env_override(&format!("{}_MONITOR_TYPE", prefix), &mut input.monitor_type)?;
+            env_override(&format!("{}_PIN", prefix), &mut input.pin)?;
+            env_override(&format!("{}_CHIP", prefix), &mut input.chip)?;
+            env_override(&format!("{}_LINE", prefix), &mut input.line)?;
+            env_override(&format!("{}_BIAS", prefix), &mut input.bias)?;
+            env_override(&format!("{}_POLARITY", prefix), &mut input.polarity)?;
+            env_override(&format!("{}_PRIORITY", prefix), &mut input.priority)?;
+            input.debounce.apply_env_with_prefix(&prefix)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn gpiod(&self) -> GpiodConfig {
+        GpiodConfig {
+            chip: self.chip.clone(),
+            line: self.line.clone(),
+            bias: self.bias.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct WebMonitorConfig {
     pub port: u16,
     pub enabled: bool,
+    /// Lets the web page override the state of a real input.
+    pub allow_override: bool,
+}
+
+impl Default for WebMonitorConfig {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            enabled: true,
+            allow_override: false,
+        }
+    }
 }
 
 impl WebMonitorConfig {
-    pub fn from_env() -> Result<Self> {
-        let port = std::env::var("NOISEBELL_WEB_MONITOR_PORT")
-            .unwrap_or_else(|_| "8080".to_string())
-            .parse::<u16>()
-            .map_err(|_| anyhow::anyhow!("Invalid web monitor port"))?;
-            
-        let enabled = std::env::var("NOISEBELL_WEB_MONITOR_ENABLED")
-            .unwrap_or_else(|_| "true".to_string())
-            .parse::<bool>()
-            .map_err(|_| anyhow::anyhow!("Invalid web monitor enabled flag"))?;
-            
-        Ok(Self {
-            port,
-            enabled,
-        })
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_WEB_MONITOR_PORT", &mut self.port)?;
+        env_override("NOISEBELL_WEB_MONITOR_ENABLED", &mut self.enabled)?;
+        env_override("NOISEBELL_WEB_MONITOR_ALLOW_OVERRIDE", &mut self.allow_override)?;
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct LoggingConfig {
     pub level: String,
     pub file_path: String,
     pub max_buffered_lines: usize,
 }
 
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file_path: "logs/noisebell.log".to_string(),
+            max_buffered_lines: 10000,
+        }
+    }
+}
+
 impl LoggingConfig {
-    pub fn from_env() -> Result<Self> {
-        let level = std::env::var("NOISEBELL_LOGGING_LEVEL")
-            .unwrap_or_else(|_| "info".to_string());
-            
-        let file_path = std::env::var("NOISEBELL_LOGGING_FILE_PATH")
-            .unwrap_or_else(|_| "logs/noisebell.log".to_string());
-            
-        let max_buffered_lines = std::env::var("NOISEBELL_LOGGING_MAX_BUFFERED_LINES")
-            .unwrap_or_else(|_| "10000".to_string())
-            .parse::<usize>()
-            .map_err(|_| anyhow::anyhow!("Invalid max buffered lines"))?;
-            
-        Ok(Self {
-            level,
-            file_path,
-            max_buffered_lines,
-        })
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_LOGGING_LEVEL", &mut self.level)?;
+        env_override("NOISEBELL_LOGGING_FILE_PATH", &mut self.file_path)?;
+        env_override("NOISEBELL_LOGGING_MAX_BUFFERED_LINES", &mut self.max_buffered_lines)?;
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct MonitorConfig {
     pub monitor_type: String,
+    /// How the states of several inputs give the published state: `any`, `all`, `majority`
+    /// or `priority`.
+    pub combine: String,
+    /// Identifies this noisebell, as `{{device_id}}` in templates and as the Home Assistant
+    /// device.
+    pub device_id: String,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            monitor_type: "web".to_string(),
+            combine: "any".to_string(),
+            device_id: "noisebell".to_string(),
+        }
+    }
 }
 
 impl MonitorConfig {
-    pub fn from_env() -> Result<Self> {
-        let monitor_type = std::env::var("NOISEBELL_MONITOR_TYPE")
-            .unwrap_or_else(|_| "web".to_string());
-            
-        Ok(Self {
-            monitor_type,
-        })
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_MONITOR_TYPE", &mut self.monitor_type)?;
+        env_override("NOISEBELL_MONITOR_COMBINE", &mut self.combine)?;
+        env_override("NOISEBELL_MONITOR_DEVICE_ID", &mut self.device_id)?;
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct EndpointConfig {
+    pub name: String,
+    /// `http`, `slack`, `matrix`, `zulip` or `discord`.
+    pub kind: String,
+    /// The endpoint for `http`, the incoming webhook for `slack` and `discord`, the homeserver
+    /// for `matrix` and the site for `zulip`.
     pub url: String,
+    /// Bearer token for `http`, access token for `matrix` and bot API key for `zulip`.
     pub api_key: Option<String>,
+    /// HTTP method for `http`.
+    pub method: String,
+    /// How `payload_template` is sent for `http`: `json`, `form` or `text`.
+    pub format: String,
+    /// Request body for `http`. Variables are escaped to suit the format.
+    pub payload_template: String,
+    /// Extra request headers for `http`. The values can use the same variables.
+    pub headers: BTreeMap<String, String>,
+    /// Shared secret for HMAC-SHA256 signing of `http` requests, see `noisebell::signing`.
+    pub signing_secret: Option<String>,
+    /// Chat message for `slack`, `matrix` and `zulip`, and the embed title for `discord`, using
+    /// the same variables.
+    pub message_template: String,
+    /// Room to post in for `matrix`, such as `!abcdefg:matrix.org`.
+    pub room_id: Option<String>,
+    /// Bot account for `zulip`.
+    pub bot_email: Option<String>,
+    pub stream: Option<String>,
+    pub topic: Option<String>,
+    /// Override the name and avatar set on the webhook for `discord`.
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Plain message text above the `discord` embed, empty for none.
+    pub content_template: String,
+    /// Description of the `discord` embed, left out when the previous state is unknown. Empty
+    /// for none.
+    pub description_template: String,
+    /// Image shown in the `discord` embed for each state.
+    pub open_image_url: Option<String>,
+    pub closed_image_url: Option<String>,
+    /// Edit one `discord` message, for example a pinned one, instead of posting a new one
+    /// every time.
+    pub edit_message: bool,
+    /// The message to edit. By default the first message posted is used. Once a replacement for
+    /// a deleted message has been posted, the one in the state file is used instead.
+    pub message_id: Option<String>,
+    /// Where the ID of the edited message is kept, `data/discord-<name>.json` by default.
+    pub state_path: Option<String>,
     pub timeout_secs: u64,
     pub retry_attempts: u32,
+    pub enabled: bool,
+    pub backoff_initial_ms: u64,
+    pub backoff_max_ms: u64,
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_reset_secs: u64,
+    /// When several events are waiting for this endpoint, send only the newest one.
+    pub coalesce: bool,
+    /// Keys set for this endpoint in the config file, which shared environment variables leave
+    /// alone.
+    #[serde(skip)]
+    pub file_keys: BTreeSet<String>,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            kind: "http".to_string(),
+            url: String::new(),
+            api_key: None,
+            method: "POST".to_string(),
+            format: "json".to_string(),
+            payload_template: r#"{"id": "{{event_id}}", "sequence": {{sequence}}, "status": "{{status}}", "reconciliation": {{reconciliation}}}"#
+                .to_string(),
+            headers: BTreeMap::new(),
+            signing_secret: None,
+            message_template: "The space is {{status}}".to_string(),
+            room_id: None,
+            bot_email: None,
+            stream: None,
+            topic: None,
+            username: None,
+            avatar_url: None,
+            content_template: String::new(),
+            description_template: "It was {{previous_status}} for {{duration}}.".to_string(),
+            open_image_url: None,
+            closed_image_url: None,
+            edit_message: false,
+            message_id: None,
+            state_path: None,
+            timeout_secs: 30,
+            retry_attempts: 3,
+            enabled: true,
+            backoff_initial_ms: 1000,
+            backoff_max_ms: 60000,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_reset_secs: 300,
+            coalesce: true,
+            file_keys: BTreeSet::new(),
+        }
+    }
 }
 
 impl EndpointConfig {
-    pub fn from_env() -> Result<Self> {
-        let url = std::env::var("NOISEBELL_ENDPOINT_URL")
-            .unwrap_or_else(|_| "https://noisebell.jetpham.com/api/status".to_string());
-            
-        let api_key = std::env::var("ENDPOINT_API_KEY").ok();
-        
-        let timeout_secs = std::env::var("NOISEBELL_ENDPOINT_TIMEOUT_SECS")
-            .unwrap_or_else(|_| "30".to_string())
-            .parse::<u64>()
-            .map_err(|_| anyhow::anyhow!("Invalid endpoint timeout"))?;
-            
-        let retry_attempts = std::env::var("NOISEBELL_ENDPOINT_RETRY_ATTEMPTS")
-            .unwrap_or_else(|_| "3".to_string())
-            .parse::<u32>()
-            .map_err(|_| anyhow::anyhow!("Invalid retry attempts"))?;
-            
-        Ok(Self {
-            url,
-            api_key,
-            timeout_secs,
-            retry_attempts,
-        })
+    /// Applies environment overrides to the list of endpoints.
+    ///
+    /// `NOISEBELL_ENDPOINTS` is a comma-separated list of endpoint names that replaces the
+    /// configured list, keeping the file settings of endpoints that appear in both. Shared
+    /// `NOISEBELL_ENDPOINT_*` variables apply to every endpoint that doesn't set the same key in
+    /// the file, and `NOISEBELL_ENDPOINT_<NAME>_*` variables to a single one, overriding the file. The legacy `NOISEBELL_ENDPOINT_URL` and `ENDPOINT_API_KEY`
+    /// apply to the endpoint named `default`.
+    pub fn apply_env_to_list(endpoints: &mut Vec<Self>) -> Result<()> {
+        if let Ok(names) = std::env::var("NOISEBELL_ENDPOINTS") {
+            let mut configured = std::mem::take(endpoints);
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                let endpoint = match configured.iter().position(|endpoint| endpoint.name == name) {
+                    Some(index) => configured.remove(index),
+                    None => Self {
+                        name: name.to_string(),
+                        ..Self::default()
+                    },
+                };
+                endpoints.push(endpoint);
+            }
+        }
+
+        for endpoint in endpoints.iter_mut() {
+            endpoint.apply_env("NOISEBELL_ENDPOINT", true)?;
+
+            if endpoint.name == "default" {
+                env_override("NOISEBELL_ENDPOINT_URL", &mut endpoint.url)?;
+                if let Ok(api_key) = std::env::var("ENDPOINT_API_KEY") {
+                    endpoint.api_key = Some(api_key);
+                }
+            }
+
+            let prefix = endpoint.env_prefix();
+            env_override(&format!("{}_KIND", prefix), &mut endpoint.kind)?;
+            env_override(&format!("{}_URL", prefix), &mut endpoint.url)?;
+            if let Ok(api_key) = std::env::var(format!("{}_API_KEY", prefix)) {
+                endpoint.api_key = Some(api_key);
+            }
+            env_override(&format!("{}_METHOD", prefix), &mut endpoint.method)?;
+            env_override(&format!("{}_FORMAT", prefix), &mut endpoint.format)?;
+            env_override(&format!("{}_PAYLOAD_TEMPLATE", prefix), &mut endpoint.payload_template)?;
+            env_override(&format!("{}_MESSAGE_TEMPLATE", prefix), &mut endpoint.message_template)?;
+            env_override(&format!("{}_CONTENT_TEMPLATE", prefix), &mut endpoint.content_template)?;
+            env_override(&format!("{}_DESCRIPTION_TEMPLATE", prefix), &mut endpoint.description_template)?;
+            env_override(&format!("{}_EDIT_MESSAGE", prefix), &mut endpoint.edit_message)?;
+            for (key, target) in [
+                ("SIGNING_SECRET", &mut endpoint.signing_secret),
+                ("ROOM_ID", &mut endpoint.room_id),
+                ("BOT_EMAIL", &mut endpoint.bot_email),
+                ("STREAM", &mut endpoint.stream),
+                ("TOPIC", &mut endpoint.topic),
+                ("USERNAME", &mut endpoint.username),
+                ("AVATAR_URL", &mut endpoint.avatar_url),
+                ("OPEN_IMAGE_URL", &mut endpoint.open_image_url),
+                ("CLOSED_IMAGE_URL", &mut endpoint.closed_image_url),
+                ("MESSAGE_ID", &mut endpoint.message_id),
+                ("STATE_PATH", &mut endpoint.state_path),
+            ] {
+                if let Ok(value) = std::env::var(format!("{}_{}", prefix, key)) {
+                    *target = Some(value);
+                }
+            }
+            endpoint.apply_env(&prefix, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Where a `discord` endpoint keeps the ID of the message it edits.
+    pub fn discord_state_path(&self) -> String {
+        self.state_path.clone().unwrap_or_else(|| format!("data/discord-{}.json", self.name))
+    }
+
+    /// Prefix of the variables that configure this endpoint only, e.g. `NOISEBELL_ENDPOINT_WIKI_BOT`.
+    pub fn env_prefix(&self) -> String {
+        format!("NOISEBELL_ENDPOINT_{}", self.name.to_uppercase().replace('-', "_"))
+    }
+
+    /// Applies the delivery settings under `prefix`. Shared variables only fill in what the
+    /// config file leaves unset for this endpoint.
+    fn apply_env(&mut self, prefix: &str, shared: bool) -> Result<()> {
+        fn apply<T: FromStr>(prefix: &str, key: &str, set_in_file: bool, target: &mut T) -> Result<()> {
+            if set_in_file {
+                return Ok(());
+            }
+            env_override(&format!("{}_{}", prefix, key.to_uppercase()), target)
+        }
+
+        let set_in_file = |key: &str| shared && self.file_keys.contains(key);
+        apply(prefix, "timeout_secs", set_in_file("timeout_secs"), &mut self.timeout_secs)?;
+        apply(prefix, "retry_attempts", set_in_file("retry_attempts"), &mut self.retry_attempts)?;
+        apply(prefix, "enabled", set_in_file("enabled"), &mut self.enabled)?;
+        apply(prefix, "backoff_initial_ms", set_in_file("backoff_initial_ms"), &mut self.backoff_initial_ms)?;
+        apply(prefix, "backoff_max_ms", set_in_file("backoff_max_ms"), &mut self.backoff_max_ms)?;
+        apply(prefix, "circuit_breaker_threshold", set_in_file("circuit_breaker_threshold"), &mut self.circuit_breaker_threshold)?;
+        apply(prefix, "circuit_breaker_reset_secs", set_in_file("circuit_breaker_reset_secs"), &mut self.circuit_breaker_reset_secs)?;
+        apply(prefix, "coalesce", set_in_file("coalesce"), &mut self.coalesce)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboxConfig {
+    pub path: String,
+    pub retry_interval_secs: u64,
+    /// How long to wait for in-flight notifications when shutting down.
+    pub shutdown_timeout_secs: u64,
+    /// Most events kept for endpoints that are behind. The oldest are dropped beyond that.
+    pub max_entries: usize,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            path: "data/outbox.jsonl".to_string(),
+            retry_interval_secs: 30,
+            shutdown_timeout_secs: 10,
+            max_entries: 1000,
+        }
+    }
+}
+
+impl OutboxConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_OUTBOX_PATH", &mut self.path)?;
+        env_override("NOISEBELL_OUTBOX_RETRY_INTERVAL_SECS", &mut self.retry_interval_secs)?;
+        env_override("NOISEBELL_OUTBOX_SHUTDOWN_TIMEOUT_SECS", &mut self.shutdown_timeout_secs)?;
+        env_override("NOISEBELL_OUTBOX_MAX_ENTRIES", &mut self.max_entries)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateConfig {
+    /// File holding the last reported state, compared with the input on startup.
+    pub path: String,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            path: "data/state.json".to_string(),
+        }
+    }
+}
+
+impl StateConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_STATE_PATH", &mut self.path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Append-only log of every state change and its delivery outcomes.
+    pub path: String,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            path: "data/history.jsonl".to_string(),
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_HISTORY_PATH", &mut self.path)?;
+        Ok(())
+    }
+}
+
+/// Static space metadata for the SpaceAPI document. The state itself comes from the input.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpaceApiConfig {
+    pub enabled: bool,
+    pub space: String,
+    pub logo: String,
+    pub url: String,
+    pub location: SpaceApiLocation,
+    /// Contact channels by SpaceAPI key, such as `email`, `irc`, `matrix` or `mastodon`.
+    pub contact: BTreeMap<String, String>,
+    /// Where to send the document on every change, such as a SpaceAPI directory.
+    pub push_url: Option<String>,
+    pub push_api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpaceApiLocation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl SpaceApiConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_SPACEAPI_ENABLED", &mut self.enabled)?;
+        env_override("NOISEBELL_SPACEAPI_SPACE", &mut self.space)?;
+        env_override("NOISEBELL_SPACEAPI_LOGO", &mut self.logo)?;
+        env_override("NOISEBELL_SPACEAPI_URL", &mut self.url)?;
+        env_override("NOISEBELL_SPACEAPI_LAT", &mut self.location.lat)?;
+        env_override("NOISEBELL_SPACEAPI_LON", &mut self.location.lon)?;
+        if let Ok(push_url) = std::env::var("NOISEBELL_SPACEAPI_PUSH_URL") {
+            self.push_url = Some(push_url);
+        }
+        if let Ok(push_api_key) = std::env::var("NOISEBELL_SPACEAPI_PUSH_API_KEY") {
+            self.push_api_key = Some(push_api_key);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// Must be unique on the broker, the device ID by default.
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topic the state is published to, retained, as `open` or `closed`. `<device_id>/status` by
+    /// default.
+    pub topic: Option<String>,
+    /// Retained `online`/`offline` topic, set to `offline` by the broker if noisebell disappears.
+    /// `<device_id>/availability` by default.
+    pub availability_topic: Option<String>,
+    pub keep_alive_secs: u64,
+    pub reconnect_delay_secs: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: None,
+            username: None,
+            password: None,
+            topic: None,
+            availability_topic: None,
+            keep_alive_secs: 30,
+            reconnect_delay_secs: 5,
+        }
+    }
+}
+
+impl MqttConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_MQTT_ENABLED", &mut self.enabled)?;
+        env_override("NOISEBELL_MQTT_HOST", &mut self.host)?;
+        env_override("NOISEBELL_MQTT_PORT", &mut self.port)?;
+        if let Ok(client_id) = std::env::var("NOISEBELL_MQTT_CLIENT_ID") {
+            self.client_id = Some(client_id);
+        }
+        if let Ok(username) = std::env::var("NOISEBELL_MQTT_USERNAME") {
+            self.username = Some(username);
+        }
+        if let Ok(password) = std::env::var("NOISEBELL_MQTT_PASSWORD") {
+            self.password = Some(password);
+        }
+        if let Ok(topic) = std::env::var("NOISEBELL_MQTT_TOPIC") {
+            self.topic = Some(topic);
+        }
+        if let Ok(availability_topic) = std::env::var("NOISEBELL_MQTT_AVAILABILITY_TOPIC") {
+            self.availability_topic = Some(availability_topic);
+        }
+        env_override("NOISEBELL_MQTT_KEEP_ALIVE_SECS", &mut self.keep_alive_secs)?;
+        env_override("NOISEBELL_MQTT_RECONNECT_DELAY_SECS", &mut self.reconnect_delay_secs)?;
+        Ok(())
+    }
+
+    pub fn client_id(&self, device_id: &str) -> String {
+        self.client_id.clone().unwrap_or_else(|| device_id.to_string())
+    }
+
+    pub fn topic(&self, device_id: &str) -> String {
+        self.topic.clone().unwrap_or_else(|| format!("{}/status", device_id))
+    }
+
+    pub fn availability_topic(&self, device_id: &str) -> String {
+        self.availability_topic.clone().unwrap_or_else(|| format!("{}/availability", device_id))
+    }
+}
+
+/// Home Assistant MQTT discovery, published through the `[mqtt]` connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeAssistantConfig {
+    pub enabled: bool,
+    pub discovery_prefix: String,
+    pub device_name: String,
+    /// `door` or `opening`.
+    pub device_class: String,
+    /// Retained JSON with the diagnostic values, `<device_id>/diagnostics` by default.
+    pub diagnostics_topic: Option<String>,
+    pub diagnostics_interval_secs: u64,
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            discovery_prefix: "homeassistant".to_string(),
+            device_name: "Noisebell".to_string(),
+            device_class: "door".to_string(),
+            diagnostics_topic: None,
+            diagnostics_interval_secs: 60,
+        }
+    }
+}
+
+impl HomeAssistantConfig {
+    pub fn apply_env(&mut self) -> Result<()> {
+        env_override("NOISEBELL_HOME_ASSISTANT_ENABLED", &mut self.enabled)?;
+        env_override("NOISEBELL_HOME_ASSISTANT_DISCOVERY_PREFIX", &mut self.discovery_prefix)?;
+        env_override("NOISEBELL_HOME_ASSISTANT_DEVICE_NAME", &mut self.device_name)?;
+        env_override("NOISEBELL_HOME_ASSISTANT_DEVICE_CLASS", &mut self.device_class)?;
+        if let Ok(diagnostics_topic) = std::env::var("NOISEBELL_HOME_ASSISTANT_DIAGNOSTICS_TOPIC") {
+            self.diagnostics_topic = Some(diagnostics_topic);
+        }
+        env_override("NOISEBELL_HOME_ASSISTANT_DIAGNOSTICS_INTERVAL_SECS", &mut self.diagnostics_interval_secs)?;
+        Ok(())
+    }
+
+    pub fn diagnostics_topic(&self, device_id: &str) -> String {
+        self.diagnostics_topic.clone().unwrap_or_else(|| format!("{}/diagnostics", device_id))
     }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self> {
+    /// Loads the configuration file, if any, and layers environment variables on top.
+    ///
+    /// An explicitly given `path` must exist, while the default `noisebell.toml` is only read
+    /// when present.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
         Self::load_env()?;
-        
-        let config = Config {
-            gpio: GpioConfig::from_env()?,
-            web_monitor: WebMonitorConfig::from_env()?,
-            logging: LoggingConfig::from_env()?,
-            monitor: MonitorConfig::from_env()?,
-            endpoint: EndpointConfig::from_env()?,
+
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => Self::default(),
         };
-        
+
+        config.apply_env()?;
         Ok(config)
     }
 
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read config file {}", path.display()))?;
+
+        // toml's error messages include the offending key and its line
+        Self::from_toml(&contents).map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Parses a config file, remembering which keys each endpoint sets.
+    fn from_toml(contents: &str) -> Result<Self, toml::de::Error> {
+        let mut config: Self = toml::from_str(contents)?;
+
+        let table: toml::Table = toml::from_str(contents)?;
+        if let Some(endpoints) = table.get("endpoints").and_then(toml::Value::as_array) {
+            for (endpoint, table) in config.endpoints.iter_mut().zip(endpoints) {
+                if let Some(table) = table.as_table() {
+                    endpoint.file_keys = table.keys().cloned().collect();
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the path given with `--config <path>` or `--config=<path>`, if any.
+    pub fn path_from_args(args: impl IntoIterator<Item = String>) -> Result<Option<PathBuf>> {
+        let mut args = args.into_iter().skip(1);
+        let mut path = None;
+
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                let value = args.next().ok_or_else(|| anyhow::anyhow!("--config requires a path"))?;
+                path = Some(PathBuf::from(value));
+            } else if let Some(value) = arg.strip_prefix("--config=") {
+                path = Some(PathBuf::from(value));
+            } else {
+                return Err(anyhow::anyhow!("Unknown argument: {}", arg));
+            }
+        }
+
+        Ok(path)
+    }
+
+    pub fn apply_env(&mut self) -> Result<()> {
+        self.gpio.apply_env()?;
+        self.gpiod.apply_env()?;
+        self.debounce.apply_env()?;
+        InputConfig::apply_env_to_list(&mut self.inputs)?;
+        self.web_monitor.apply_env()?;
+        self.logging.apply_env()?;
+        self.monitor.apply_env()?;
+        EndpointConfig::apply_env_to_list(&mut self.endpoints)?;
+        self.outbox.apply_env()?;
+        self.state.apply_env()?;
+        self.history.apply_env()?;
+        self.spaceapi.apply_env()?;
+        self.mqtt.apply_env()?;
+        self.home_assistant.apply_env()?;
+        Ok(())
+    }
+
     pub fn load_env() -> Result<()> {
         // Try to load from .env file, but don't fail if it doesn't exist
         match dotenv() {
@@ -173,23 +908,604 @@ impl Config {
         }
     }
 
+    /// Legacy endpoint variables that are set but have no effect, because no endpoint is named
+    /// `default`.
+    pub fn ignored_legacy_env(&self) -> Vec<&'static str> {
+        if self.endpoints.iter().any(|endpoint| endpoint.name == "default") {
+            return Vec::new();
+        }
+
+        ["NOISEBELL_ENDPOINT_URL", "ENDPOINT_API_KEY"]
+            .into_iter()
+            .filter(|key| std::env::var(key).is_ok())
+            .collect()
+    }
+
     pub fn validate(&self) -> Result<()> {
         if self.gpio.pin > 40 {
-            return Err(anyhow::anyhow!("GPIO pin must be between 1-40"));
+            return Err(anyhow::anyhow!("gpio.pin: GPIO pin must be between 1-40"));
+        }
+
+        if self.gpio.debounce_delay_secs == 0 {
+            return Err(anyhow::anyhow!("gpio.debounce_delay_secs: Debounce delay must be greater than 0"));
+        }
+
+        if self.debounce.flap_max_changes > 0 && self.debounce.flap_window_secs == 0 {
+            return Err(anyhow::anyhow!("debounce.flap_window_secs: Flap window must be greater than 0 when flap suppression is on"));
+        }
+
+        self.monitor.combine
+            .parse::<crate::inputs::CombineRule>()
+            .map_err(|e| anyhow::anyhow!("monitor.combine: {}", e))?;
+
+        if self.inputs.is_empty() {
+            if !["gpio", "gpiod", "web"].contains(&self.monitor.monitor_type.as_str()) {
+                return Err(anyhow::anyhow!("monitor.monitor_type: Unknown monitor type: {}", self.monitor.monitor_type));
+            }
+
+            if self.monitor.monitor_type == "gpiod" {
+                if self.gpiod.chip.is_empty() {
+                    return Err(anyhow::anyhow!("gpiod.chip: GPIO chip must not be empty"));
+                }
+                if self.gpiod.line.is_empty() {
+                    return Err(anyhow::anyhow!("gpiod.line: GPIO line must be an offset or a line name"));
+                }
+                if !["pull_up", "pull_down", "disabled", "as_is"].contains(&self.gpiod.bias.as_str()) {
+                    return Err(anyhow::anyhow!("gpiod.bias: Unknown bias {}, expected pull_up, pull_down, disabled or as_is", self.gpiod.bias));
+                }
+            }
+
+            if self.monitor.monitor_type == "web" && !self.web_monitor.enabled {
+                return Err(anyhow::anyhow!("web_monitor.enabled: The web monitor type needs the web server to be enabled"));
+            }
+        } else {
+            self.validate_inputs()?;
+        }
+
+        if self.endpoints.is_empty() {
+            return Err(anyhow::anyhow!("endpoints: At least one endpoint must be configured"));
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for endpoint in &self.endpoints {
+            if endpoint.name.is_empty() {
+                return Err(anyhow::anyhow!("endpoints.name: Every endpoint must have a name"));
+            }
+
+            if !names.insert(endpoint.name.as_str()) {
+                return Err(anyhow::anyhow!("endpoints.name: Duplicate endpoint name: {}", endpoint.name));
+            }
+
+            if endpoint.url.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "endpoints.url: Endpoint '{}' has no URL, set it in the config file or with {}_URL",
+                    endpoint.name,
+                    endpoint.env_prefix()
+                ));
+            }
+
+            url::Url::parse(&endpoint.url)
+                .map_err(|e| anyhow::anyhow!("endpoints.url: Invalid URL for endpoint '{}': {}", endpoint.name, e))?;
+
+            Self::validate_endpoint_kind(endpoint)?;
+
+            if endpoint.retry_attempts == 0 {
+                return Err(anyhow::anyhow!("endpoints.retry_attempts: Endpoint '{}' must have at least 1 retry attempt", endpoint.name));
+            }
+
+            if endpoint.backoff_max_ms < endpoint.backoff_initial_ms {
+                return Err(anyhow::anyhow!("endpoints.backoff_max_ms: Endpoint '{}' max backoff must not be less than its initial backoff", endpoint.name));
+            }
+
+            if endpoint.circuit_breaker_threshold == 0 {
+                return Err(anyhow::anyhow!("endpoints.circuit_breaker_threshold: Endpoint '{}' circuit breaker threshold must be greater than 0", endpoint.name));
+            }
+        }
+
+        if self.outbox.retry_interval_secs == 0 {
+            return Err(anyhow::anyhow!("outbox.retry_interval_secs: Outbox retry interval must be greater than 0"));
+        }
+
+        if self.outbox.max_entries == 0 {
+            return Err(anyhow::anyhow!("outbox.max_entries: Outbox must be able to hold at least 1 event"));
+        }
+
+        if self.spaceapi.enabled {
+            self.validate_spaceapi()?;
+        }
+
+        if self.mqtt.enabled {
+            self.validate_mqtt()?;
+        }
+
+        if self.home_assistant.enabled {
+            self.validate_home_assistant()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the settings each kind of endpoint needs on top of its URL.
+    fn validate_endpoint_kind(endpoint: &EndpointConfig) -> Result<()> {
+        let required: &[(&str, &Option<String>)] = match endpoint.kind.as_str() {
+            "http" => return Self::validate_http_endpoint(endpoint),
+            "slack" => &[],
+            "discord" => {
+                Self::validate_discord_endpoint(endpoint)?;
+                &[]
+            }
+            "matrix" => &[("api_key", &endpoint.api_key), ("room_id", &endpoint.room_id)],
+            "zulip" => &[
+                ("api_key", &endpoint.api_key),
+                ("bot_email", &endpoint.bot_email),
+                ("stream", &endpoint.stream),
+                ("topic", &endpoint.topic),
+            ],
+            kind => {
+                return Err(anyhow::anyhow!(
+                    "endpoints.kind: Endpoint '{}' has unknown kind {}, expected http, slack, matrix, zulip or discord",
+                    endpoint.name,
+                    kind
+                ));
+            }
+        };
+
+        for (key, value) in required {
+            if value.as_deref().is_none_or(str::is_empty) {
+                return Err(anyhow::anyhow!(
+                    "endpoints.{}: Required for {} endpoint '{}', set it in the config file or with {}_{}",
+                    key,
+                    endpoint.kind,
+                    endpoint.name,
+                    endpoint.env_prefix(),
+                    key.to_uppercase()
+                ));
+            }
+        }
+
+        if endpoint.message_template.is_empty() {
+            return Err(anyhow::anyhow!("endpoints.message_template: {} endpoint '{}' needs a message", endpoint.kind, endpoint.name));
+        }
+
+        Ok(())
+    }
+
+    fn validate_discord_endpoint(endpoint: &EndpointConfig) -> Result<()> {
+        if !endpoint.url.starts_with("http://") && !endpoint.url.starts_with("https://") {
+            return Err(anyhow::anyhow!("endpoints.url: Discord endpoint '{}' must have an http or https webhook URL", endpoint.name));
+        }
+
+        for (key, value) in [
+            ("avatar_url", &endpoint.avatar_url),
+            ("open_image_url", &endpoint.open_image_url),
+            ("closed_image_url", &endpoint.closed_image_url),
+        ] {
+            if let Some(value) = value {
+                url::Url::parse(value)
+                    .map_err(|e| anyhow::anyhow!("endpoints.{}: Invalid URL for endpoint '{}': {}", key, endpoint.name, e))?;
+            }
+        }
+
+        if endpoint.message_id.is_some() && !endpoint.edit_message {
+            return Err(anyhow::anyhow!("endpoints.message_id: Endpoint '{}' only uses it with edit_message enabled", endpoint.name));
+        }
+
+        Ok(())
+    }
+
+    fn validate_http_endpoint(endpoint: &EndpointConfig) -> Result<()> {
+        if reqwest::Method::from_bytes(endpoint.method.to_uppercase().as_bytes()).is_err() {
+            return Err(anyhow::anyhow!("endpoints.method: Endpoint '{}' has invalid HTTP method {}", endpoint.name, endpoint.method));
+        }
+
+        match endpoint.format.as_str() {
+            // Check that the template renders to JSON, using a made up event
+            "json" => {
+                let now = chrono::Utc::now();
+                let sample = crate::outbox::OutboxEntry {
+                    sequence: 1,
+                    id: uuid::Uuid::new_v4(),
+                    timestamp: now,
+                    event: crate::StatusEvent::Open,
+                    reconciliation: false,
+                    previous: Some(crate::state_store::LastState { status: crate::StatusEvent::Closed, timestamp: now }),
+                };
+                let sample = crate::notifier::variables(&sample, "noisebell");
+                let rendered = crate::template::render_escaped(&endpoint.payload_template, &sample, crate::template::json_escape);
+                serde_json::from_str::<serde_json::Value>(&rendered).map_err(|e| {
+                    anyhow::anyhow!("endpoints.payload_template: Endpoint '{}' payload is not valid JSON: {}", endpoint.name, e)
+                })?;
+            }
+            "form" | "text" => {}
+            format => {
+                return Err(anyhow::anyhow!(
+                    "endpoints.format: Endpoint '{}' has unknown format {}, expected json, form or text",
+                    endpoint.name,
+                    format
+                ));
+            }
+        }
+
+        if endpoint.signing_secret.as_deref().is_some_and(str::is_empty) {
+            return Err(anyhow::anyhow!("endpoints.signing_secret: Endpoint '{}' signing secret must not be empty", endpoint.name));
+        }
+
+        for (name, value) in &endpoint.headers {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(anyhow::anyhow!("endpoints.headers: Endpoint '{}' has invalid header name {}", endpoint.name, name));
+            }
+            if reqwest::header::HeaderValue::from_str(value).is_err() {
+                return Err(anyhow::anyhow!("endpoints.headers: Endpoint '{}' has invalid value for header {}", endpoint.name, name));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_home_assistant(&self) -> Result<()> {
+        let home_assistant = &self.home_assistant;
+
+        if !self.mqtt.enabled {
+            return Err(anyhow::anyhow!("home_assistant.enabled: Home Assistant discovery needs MQTT to be enabled"));
+        }
+
+        if !["door", "opening"].contains(&home_assistant.device_class.as_str()) {
+            return Err(anyhow::anyhow!("home_assistant.device_class: Must be door or opening, not {}", home_assistant.device_class));
+        }
+
+        for (key, topic) in [
+            ("discovery_prefix", Some(&home_assistant.discovery_prefix)),
+            ("diagnostics_topic", home_assistant.diagnostics_topic.as_ref()),
+        ] {
+            if topic.is_some_and(|topic| topic.is_empty() || topic.contains(['+', '#'])) {
+                return Err(anyhow::anyhow!("home_assistant.{}: Must be a topic name without wildcards", key));
+            }
+        }
+
+        if home_assistant.diagnostics_interval_secs == 0 {
+            return Err(anyhow::anyhow!("home_assistant.diagnostics_interval_secs: Diagnostics interval must be greater than 0"));
+        }
+
+        Ok(())
+    }
+
+    fn validate_mqtt(&self) -> Result<()> {
+        let mqtt = &self.mqtt;
+
+        // The client ID, topics and Home Assistant discovery IDs are derived from it, and
+        // discovery topics only allow these characters in the node and object IDs
+        let device_id = &self.monitor.device_id;
+        if device_id.is_empty() || !device_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("monitor.device_id: Must only contain letters, digits, '_' and '-' for MQTT"));
+        }
+
+        if mqtt.host.is_empty() {
+            return Err(anyhow::anyhow!("mqtt.host: Required when MQTT is enabled"));
+        }
+
+        if mqtt.client_id.as_ref().is_some_and(String::is_empty) {
+            return Err(anyhow::anyhow!("mqtt.client_id: Must not be empty"));
+        }
+
+        for (key, topic) in [("topic", &mqtt.topic), ("availability_topic", &mqtt.availability_topic)] {
+            if topic.as_ref().is_some_and(|topic| topic.is_empty() || topic.contains(['+', '#'])) {
+                return Err(anyhow::anyhow!("mqtt.{}: Must be a topic name without wildcards", key));
+            }
+        }
+
+        if mqtt.keep_alive_secs == 0 {
+            return Err(anyhow::anyhow!("mqtt.keep_alive_secs: Keep alive must be greater than 0"));
+        }
+
+        if mqtt.reconnect_delay_secs == 0 {
+            return Err(anyhow::anyhow!("mqtt.reconnect_delay_secs: Reconnect delay must be greater than 0"));
+        }
+
+        Ok(())
+    }
+
+    fn validate_spaceapi(&self) -> Result<()> {
+        let spaceapi = &self.spaceapi;
+
+        if !self.web_monitor.enabled && spaceapi.push_url.is_none() {
+            return Err(anyhow::anyhow!("spaceapi.enabled: SpaceAPI needs the web server to be enabled or a push_url to be set"));
+        }
+
+        for (key, value) in [("space", &spaceapi.space), ("logo", &spaceapi.logo), ("url", &spaceapi.url)] {
+            if value.is_empty() {
+                return Err(anyhow::anyhow!("spaceapi.{}: Required when SpaceAPI is enabled", key));
+            }
+        }
+
+        for (key, value) in [("logo", &spaceapi.logo), ("url", &spaceapi.url)] {
+            url::Url::parse(value).map_err(|e| anyhow::anyhow!("spaceapi.{}: Invalid URL: {}", key, e))?;
+        }
+
+        if !(-90.0..=90.0).contains(&spaceapi.location.lat) {
+            return Err(anyhow::anyhow!("spaceapi.location.lat: Latitude must be between -90 and 90"));
         }
 
-        if self.gpio.debounce_delay_secs <= 0 {
-            return Err(anyhow::anyhow!("Debounce delay must be greater than 0"));
+        if !(-180.0..=180.0).contains(&spaceapi.location.lon) {
+            return Err(anyhow::anyhow!("spaceapi.location.lon: Longitude must be between -180 and 180"));
         }
 
-        if !["gpio", "web"].contains(&self.monitor.monitor_type.as_str()) {
-            return Err(anyhow::anyhow!("Unknown monitor type: {}", self.monitor.monitor_type));
+        if let Some(push_url) = &spaceapi.push_url {
+            url::Url::parse(push_url).map_err(|e| anyhow::anyhow!("spaceapi.push_url: Invalid URL: {}", e))?;
         }
 
         Ok(())
     }
 
-    pub fn get_debounce_delay(&self) -> Duration {
-        Duration::from_secs(self.gpio.debounce_delay_secs)
+    fn validate_inputs(&self) -> Result<()> {
+        let mut names = std::collections::HashSet::new();
+        for input in &self.inputs {
+            if input.name.is_empty() {
+                return Err(anyhow::anyhow!("inputs.name: Every input must have a name"));
+            }
+
+            if !names.insert(input.name.as_str()) {
+                return Err(anyhow::anyhow!("inputs.name: Duplicate input name: {}", input.name));
+            }
+
+            if !["normal", "inverted"].contains(&input.polarity.as_str()) {
+                return Err(anyhow::anyhow!("inputs.polarity: Input '{}' polarity must be normal or inverted", input.name));
+            }
+
+            match input.monitor_type.as_str() {
+                "gpio" if input.pin > 40 => {
+                    return Err(anyhow::anyhow!("inputs.pin: Input '{}' GPIO pin must be between 1-40", input.name));
+                }
+                "gpiod" => {
+                    if input.chip.is_empty() {
+                        return Err(anyhow::anyhow!("inputs.chip: Input '{}' GPIO chip must not be empty", input.name));
+                    }
+                    if input.line.is_empty() {
+                        return Err(anyhow::anyhow!("inputs.line: Input '{}' GPIO line must be an offset or a line name", input.name));
+                    }
+                    if !["pull_up", "pull_down", "disabled", "as_is"].contains(&input.bias.as_str()) {
+                        return Err(anyhow::anyhow!("inputs.bias: Input '{}' has unknown bias {}, expected pull_up, pull_down, disabled or as_is", input.name, input.bias));
+                    }
+                }
+                "gpio" => {}
+                "web" => {
+                    if !self.web_monitor.enabled {
+                        return Err(anyhow::anyhow!("web_monitor.enabled: Input '{}' needs the web server to be enabled", input.name));
+                    }
+                    if input.polarity != "normal" {
+                        return Err(anyhow::anyhow!("inputs.polarity: Input '{}' is the web page, which cannot be inverted", input.name));
+                    }
+                }
+                monitor_type => {
+                    return Err(anyhow::anyhow!("inputs.monitor_type: Input '{}' has unknown monitor type: {}", input.name, monitor_type));
+                }
+            }
+
+            if input.debounce.flap_max_changes > 0 && input.debounce.flap_window_secs == 0 {
+                return Err(anyhow::anyhow!("inputs.debounce.flap_window_secs: Input '{}' flap window must be greater than 0 when flap suppression is on", input.name));
+            }
+        }
+
+        // There is only one switch on the web page
+        if self.inputs.iter().filter(|input| input.monitor_type == "web").count() > 1 {
+            return Err(anyhow::anyhow!("inputs.monitor_type: Only one input can be the web page"));
+        }
+
+        Ok(())
     }
-} 
\ No newline at end of file
+
+    /// The configured inputs, or the single input described by `[monitor]`, `[gpio]`, `[gpiod]`
+    /// and `[debounce]` when there is no `[[inputs]]` list. That input is named after its
+    /// monitor type.
+    pub fn inputs(&self) -> Vec<InputConfig> {
+        if !self.inputs.is_empty() {
+            return self.inputs.clone();
+        }
+
+        vec![InputConfig {
+            name: self.monitor.monitor_type.clone(),
+            monitor_type: self.monitor.monitor_type.clone(),
+            pin: self.gpio.pin,
+            chip: self.gpiod.chip.clone(),
+            line: self.gpiod.line.clone(),
+            bias: self.gpiod.bias.clone(),
+            polarity: "normal".to_string(),
+            priority: 0,
+            debounce: self.debounce.clone(),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+    use tempfile::TempDir;
+
+    // The environment is shared by every test thread
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Environment variables set for one test, removed again when dropped.
+    struct Env {
+        keys: Vec<&'static str>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Env {
+        fn set(vars: &[(&'static str, &str)]) -> Self {
+            let lock = ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+            for (key, value) in vars {
+                std::env::set_var(key, value);
+            }
+            Self {
+                keys: vars.iter().map(|(key, _)| *key).collect(),
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for Env {
+        fn drop(&mut self) {
+            for key in &self.keys {
+                std::env::remove_var(key);
+            }
+        }
+    }
+
+    const FILE: &str = r#"
+        [outbox]
+        max_entries = 10
+
+        [[endpoints]]
+        name = "a"
+        url = "https://a.example/hook"
+        timeout_secs = 10
+
+        [[endpoints]]
+        name = "b"
+        url = "https://b.example/hook"
+    "#;
+
+    fn load(contents: &str) -> Result<Config> {
+        let mut config = Config::from_toml(contents)?;
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn endpoint<'a>(config: &'a Config, name: &str) -> &'a EndpointConfig {
+        config.endpoints.iter().find(|endpoint| endpoint.name == name).unwrap()
+    }
+
+    #[test]
+    fn reads_file_values() {
+        let _env = Env::set(&[]);
+        let config = load(FILE).unwrap();
+
+        assert_eq!(config.outbox.max_entries, 10);
+        assert_eq!(config.outbox.retry_interval_secs, OutboxConfig::default().retry_interval_secs);
+        assert_eq!(config.endpoints.len(), 2);
+        assert_eq!(endpoint(&config, "a").timeout_secs, 10);
+        assert_eq!(endpoint(&config, "b").timeout_secs, EndpointConfig::default().timeout_secs);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let _env = Env::set(&[("NOISEBELL_OUTBOX_MAX_ENTRIES", "5"), ("NOISEBELL_ENDPOINT_B_URL", "https://c.example/hook")]);
+        let config = load(FILE).unwrap();
+
+        assert_eq!(config.outbox.max_entries, 5);
+        assert_eq!(endpoint(&config, "b").url, "https://c.example/hook");
+    }
+
+    #[test]
+    fn shared_endpoint_env_fills_in_unset_keys() {
+        let _env = Env::set(&[
+            ("NOISEBELL_ENDPOINT_TIMEOUT_SECS", "20"),
+            ("NOISEBELL_ENDPOINT_RETRY_ATTEMPTS", "7"),
+            ("NOISEBELL_ENDPOINT_B_RETRY_ATTEMPTS", "9"),
+        ]);
+        let config = load(FILE).unwrap();
+
+        // Set in the file for a, so the shared value doesn't apply
+        assert_eq!(endpoint(&config, "a").timeout_secs, 10);
+        assert_eq!(endpoint(&config, "b").timeout_secs, 20);
+        assert_eq!(endpoint(&config, "a").retry_attempts, 7);
+        assert_eq!(endpoint(&config, "b").retry_attempts, 9);
+    }
+
+    #[test]
+    fn endpoint_env_overrides_file() {
+        let _env = Env::set(&[("NOISEBELL_ENDPOINT_TIMEOUT_SECS", "20"), ("NOISEBELL_ENDPOINT_A_TIMEOUT_SECS", "40")]);
+        let config = load(FILE).unwrap();
+
+        assert_eq!(endpoint(&config, "a").timeout_secs, 40);
+    }
+
+    #[test]
+    fn endpoint_list_from_env() {
+        let _env = Env::set(&[
+            ("NOISEBELL_ENDPOINTS", "b, new"),
+            ("NOISEBELL_ENDPOINT_TIMEOUT_SECS", "20"),
+            ("NOISEBELL_ENDPOINT_NEW_KIND", "slack"),
+            ("NOISEBELL_ENDPOINT_NEW_URL", "https://hooks.example/new"),
+        ]);
+        let config = load(FILE).unwrap();
+
+        let names: Vec<&str> = config.endpoints.iter().map(|endpoint| endpoint.name.as_str()).collect();
+        assert_eq!(names, ["b", "new"]);
+        assert_eq!(endpoint(&config, "b").url, "https://b.example/hook");
+        assert_eq!(endpoint(&config, "new").kind, "slack");
+        assert_eq!(endpoint(&config, "new").timeout_secs, 20);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("noisebell.toml");
+        std::fs::write(&path, "[outbox]\nmax_entriez = 10\n").unwrap();
+
+        let error = Config::from_file(&path).unwrap_err().to_string();
+        assert!(error.contains(&path.display().to_string()), "{}", error);
+        assert!(error.contains("max_entriez"), "{}", error);
+    }
+
+    #[test]
+    fn explicit_path_must_exist() {
+        let _env = Env::set(&[]);
+        let dir = TempDir::new().unwrap();
+        assert!(Config::load(Some(&dir.path().join("missing.toml"))).is_err());
+    }
+
+    #[test]
+    fn bad_env_value_names_variable() {
+        let _env = Env::set(&[("NOISEBELL_ENDPOINT_A_RETRY_ATTEMPTS", "lots")]);
+
+        let error = load(FILE).unwrap_err().to_string();
+        assert_eq!(error, "Invalid value for NOISEBELL_ENDPOINT_A_RETRY_ATTEMPTS: lots");
+    }
+
+    #[test]
+    fn warns_about_legacy_env_without_default_endpoint() {
+        let _env = Env::set(&[("NOISEBELL_ENDPOINT_URL", "https://legacy.example/hook"), ("ENDPOINT_API_KEY", "key")]);
+
+        let config = load(FILE).unwrap();
+        assert_eq!(config.ignored_legacy_env(), ["NOISEBELL_ENDPOINT_URL", "ENDPOINT_API_KEY"]);
+
+        let config = load("[[endpoints]]\nname = \"default\"\n").unwrap();
+        assert!(config.ignored_legacy_env().is_empty());
+        assert_eq!(config.endpoints[0].url, "https://legacy.example/hook");
+        assert_eq!(config.endpoints[0].api_key.as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn mqtt_names_follow_device_id() {
+        let _env = Env::set(&[("NOISEBELL_MONITOR_DEVICE_ID", "upstairs")]);
+        let config = load("[mqtt]\nenabled = true\navailability_topic = \"space/online\"\n").unwrap();
+        let device_id = &config.monitor.device_id;
+
+        assert_eq!(config.mqtt.client_id(device_id), "upstairs");
+        assert_eq!(config.mqtt.topic(device_id), "upstairs/status");
+        assert_eq!(config.mqtt.availability_topic(device_id), "space/online");
+        assert_eq!(config.home_assistant.diagnostics_topic(device_id), "upstairs/diagnostics");
+    }
+
+    #[test]
+    fn mqtt_needs_device_id_usable_in_topics() {
+        let _env = Env::set(&[("NOISEBELL_MONITOR_DEVICE_ID", "up/stairs")]);
+        let config = load(&format!("{}\n[mqtt]\nenabled = true\n", FILE)).unwrap();
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.starts_with("monitor.device_id:"), "{}", error);
+    }
+
+    #[test]
+    fn reads_config_path_from_args() {
+        let args = |args: &[&str]| Config::path_from_args(args.iter().map(|arg| arg.to_string()));
+
+        assert_eq!(args(&["noisebell"]).unwrap(), None);
+        assert_eq!(args(&["noisebell", "--config", "a.toml"]).unwrap(), Some(PathBuf::from("a.toml")));
+        assert_eq!(args(&["noisebell", "--config=b.toml"]).unwrap(), Some(PathBuf::from("b.toml")));
+        assert!(args(&["noisebell", "--config"]).is_err());
+        assert!(args(&["noisebell", "--verbose"]).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::StatusEvent;
+
+/// How changes of an input are filtered before they are published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// How long the input must stay open before the change counts. Every bounce restarts it.
+    pub open_settle: Duration,
+    pub close_settle: Duration,
+    /// How long a settled state must last before it is announced.
+    pub min_open: Duration,
+    pub min_closed: Duration,
+    /// Announced changes allowed within `flap_window` before announcements are held back.
+    /// Zero turns flap suppression off.
+    pub flap_max_changes: u32,
+    /// Also how long the input must stay settled for a flapping input to be announced again.
+    pub flap_window: Duration,
+}
+
+impl Settings {
+    fn settle(&self, status: StatusEvent) -> Duration {
+        match status {
+            StatusEvent::Open => self.open_settle,
+            StatusEvent::Closed => self.close_settle,
+        }
+    }
+
+    fn min_duration(&self, status: StatusEvent) -> Duration {
+        match status {
+            StatusEvent::Open => self.min_open,
+            StatusEvent::Closed => self.min_closed,
+        }
+    }
+}
+
+/// Turns the raw states of an input into announced ones, in three steps:
+///
+/// 1. A change settles once the input has stayed in the new state for the settle time of that
+///    direction, so bounces and short glitches never get further.
+/// 2. A settled state is announced once it has lasted the minimum duration for that state.
+/// 3. When more than `flap_max_changes` would be announced within `flap_window`, the input is
+///    flapping and the last announced state is held until the input has been settled for a
+///    whole window.
+struct Debouncer {
+    settings: Settings,
+    raw: StatusEvent,
+    raw_since: Instant,
+    settled: StatusEvent,
+    settled_since: Instant,
+    announced: StatusEvent,
+    /// When recent changes were announced, for flap detection.
+    announcements: VecDeque<Instant>,
+    flapping: bool,
+}
+
+impl Debouncer {
+    fn new(settings: Settings, initial: StatusEvent, now: Instant) -> Self {
+        Self {
+            settings,
+            raw: initial,
+            raw_since: now,
+            settled: initial,
+            settled_since: now,
+            announced: initial,
+            announcements: VecDeque::new(),
+            flapping: false,
+        }
+    }
+
+    fn input(&mut self, status: StatusEvent, now: Instant) {
+        if status != self.raw {
+            self.raw = status;
+            self.raw_since = now;
+        }
+    }
+
+    /// When [`poll`](Self::poll) has something to do next, if anything.
+    fn deadline(&self) -> Option<Instant> {
+        if self.raw != self.settled {
+            return Some(self.raw_since + self.settings.settle(self.raw));
+        }
+        if self.flapping {
+            return Some(self.settled_since + self.settings.flap_window);
+        }
+        if self.settled != self.announced {
+            return Some(self.settled_since + self.settings.min_duration(self.settled));
+        }
+        None
+    }
+
+    /// Moves the state along to `now`, returning a state to announce if there is one.
+    fn poll(&mut self, now: Instant) -> Option<StatusEvent> {
+        if self.raw != self.settled && now >= self.raw_since + self.settings.settle(self.raw) {
+            self.settled = self.raw;
+            self.settled_since = now;
+        }
+
+        if self.flapping {
+            if now < self.settled_since + self.settings.flap_window {
+                return None;
+            }
+            info!("Input settled on {} after flapping", self.settled);
+            self.flapping = false;
+            self.announcements.clear();
+            return self.announce(now);
+        }
+
+        if self.settled == self.announced || now < self.settled_since + self.settings.min_duration(self.settled) {
+            return None;
+        }
+
+        if self.settings.flap_max_changes > 0 {
+            let window = self.settings.flap_window;
+            self.announcements.retain(|announced_at| now.duration_since(*announced_at) < window);
+            if self.announcements.len() >= self.settings.flap_max_changes as usize {
+                warn!(
+                    "Input changed more than {} times in {}s, holding {} state until it settles",
+                    self.settings.flap_max_changes,
+                    window.as_secs(),
+                    self.announced
+                );
+                self.flapping = true;
+                return None;
+            }
+        }
+
+        self.announce(now)
+    }
+
+    fn announce(&mut self, now: Instant) -> Option<StatusEvent> {
+        if self.settled == self.announced {
+            return None;
+        }
+        self.announced = self.settled;
+        self.announcements.push_back(now);
+        Some(self.announced)
+    }
+}
+
+/// Applies the debounce rules to a monitor's raw state changes. `initial` is the state the input
+/// is in when the stream starts.
+///
+/// Works on any stream of states, so every kind of monitor is filtered the same way.
+pub fn debounce(
+    events: BoxStream<'static, StatusEvent>,
+    settings: Settings,
+    initial: StatusEvent,
+) -> BoxStream<'static, StatusEvent> {
+    let debouncer = Debouncer::new(settings, initial, Instant::now());
+
+    stream::unfold((events.fuse(), debouncer), |(mut events, mut debouncer)| async move {
+        loop {
+            if let Some(status) = debouncer.poll(Instant::now()) {
+                return Some((status, (events, debouncer)));
+            }
+
+            let deadline = debouncer.deadline();
+            tokio::select! {
+                event = events.next() => match event {
+                    Some(status) => debouncer.input(status, Instant::now()),
+                    // The input is gone, but changes already seen still count once they are due
+                    None => tokio::time::sleep_until(deadline?).await,
+                },
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {}
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+
+    use StatusEvent::{Closed, Open};
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn settle(millis: u64) -> Settings {
+        Settings {
+            open_settle: ms(millis),
+            close_settle: ms(millis),
+            min_open: Duration::ZERO,
+            min_closed: Duration::ZERO,
+            flap_max_changes: 0,
+            flap_window: Duration::from_secs(60),
+        }
+    }
+
+    /// Feeds `(delay, state)` steps into the debouncer and returns what it announced, with the
+    /// time since the start each was announced at.
+    async fn run(settings: Settings, initial: StatusEvent, script: &[(u64, StatusEvent)]) -> Vec<(u64, StatusEvent)> {
+        let (sender, receiver) = mpsc::unbounded();
+        let script = script.to_vec();
+        tokio::spawn(async move {
+            for (delay, status) in script {
+                tokio::time::sleep(ms(delay)).await;
+                let _ = sender.unbounded_send(status);
+            }
+        });
+
+        let start = Instant::now();
+        debounce(receiver.boxed(), settings, initial)
+            .map(|status| (start.elapsed().as_millis() as u64, status))
+            .collect()
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn announces_once_settled() {
+        let events = run(settle(50), Closed, &[(100, Open), (10, Closed), (10, Open)]).await;
+        assert_eq!(events, vec![(170, Open)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ignores_glitches() {
+        let events = run(settle(50), Closed, &[(100, Open), (49, Closed), (100, Open), (20, Closed)]).await;
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_settings_pass_changes_through() {
+        let events = run(settle(0), Closed, &[(10, Open), (10, Closed), (10, Closed), (10, Open)]).await;
+        assert_eq!(events, vec![(10, Open), (20, Closed), (40, Open)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn settle_times_differ_by_direction() {
+        let settings = Settings {
+            open_settle: ms(20),
+            close_settle: ms(500),
+            ..settle(0)
+        };
+        let events = run(settings, Closed, &[(100, Open), (100, Closed), (1000, Open)]).await;
+        assert_eq!(events, vec![(120, Open), (700, Closed), (1220, Open)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closed_needs_minimum_duration() {
+        let settings = Settings {
+            min_closed: Duration::from_secs(120),
+            ..settle(50)
+        };
+        // Closed for a minute does not count, two minutes does
+        let events = run(settings, Open, &[(1000, Closed), (60_000, Open), (1000, Closed)]).await;
+        assert_eq!(events, vec![(62_000 + 50 + 120_000, Closed)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_glitches_do_not_reset_minimum_duration() {
+        let settings = Settings {
+            min_closed: Duration::from_secs(10),
+            ..settle(50)
+        };
+        let events = run(settings, Open, &[(0, Closed), (5000, Open), (10, Closed)]).await;
+        assert_eq!(events, vec![(10_050, Closed)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flapping_is_held_until_it_settles() {
+        let settings = Settings {
+            flap_max_changes: 3,
+            flap_window: Duration::from_secs(60),
+            ..settle(0)
+        };
+        let mut script = Vec::new();
+        for _ in 0..5 {
+            script.push((1000, Open));
+            script.push((1000, Closed));
+        }
+        let events = run(settings, Closed, &script).await;
+
+        // Three changes get through, then nothing until the input has been closed for a minute
+        assert_eq!(events, vec![(1000, Open), (2000, Closed), (3000, Open), (70_000, Closed)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flapping_back_to_announced_state_is_not_repeated() {
+        let settings = Settings {
+            flap_max_changes: 2,
+            flap_window: Duration::from_secs(60),
+            ..settle(0)
+        };
+        let events = run(settings, Closed, &[(1000, Open), (1000, Closed), (1000, Open), (1000, Closed)]).await;
+        assert_eq!(events, vec![(1000, Open), (2000, Closed)]);
+    }
+}
@@ -0,0 +1,421 @@
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::Client;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{info, error, warn};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{
+    StatusEvent,
+    config::EndpointConfig,
+    notifier::{self, AttemptError, Notifier},
+    outbox::OutboxEntry,
+};
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("Unknown endpoint: {0}")]
+    UnknownEndpoint(String),
+    #[error("Endpoint rejected the request: {0}")]
+    Rejected(AttemptError),
+    #[error("Circuit breaker is open, next attempt in {}s", .0.as_secs())]
+    CircuitOpen(Duration),
+    #[error("Failed after {attempts} attempts: {last_error}")]
+    Exhausted { attempts: u32, last_error: AttemptError },
+    #[error("Shutting down before the endpoint was notified")]
+    ShuttingDown,
+}
+
+impl NotifyError {
+    /// How long the endpoint asked us to wait before trying again, if it said so.
+    pub fn retry_in(&self) -> Option<Duration> {
+        match self {
+            NotifyError::CircuitOpen(retry_in) => Some(*retry_in),
+            NotifyError::Exhausted { last_error, .. } => last_error.retry_after(),
+            NotifyError::UnknownEndpoint(_) | NotifyError::Rejected(_) | NotifyError::ShuttingDown => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Snapshot of an endpoint's delivery health, for monitoring.
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointHealth {
+    pub name: String,
+    pub kind: String,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// Stops sending requests to an endpoint after repeated failures, then lets a single trial
+/// request through once `reset_after` has passed.
+struct CircuitBreaker {
+    threshold: u32,
+    reset_after: Duration,
+    state: CircuitState,
+    opened_at: Option<Instant>,
+    consecutive_failures: u32,
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, reset_after: Duration) -> Self {
+        Self {
+            threshold,
+            reset_after,
+            state: CircuitState::Closed,
+            opened_at: None,
+            consecutive_failures: 0,
+            last_success: None,
+            last_failure: None,
+            last_error: None,
+        }
+    }
+
+    /// Returns how long requests are still blocked for, if the circuit is open.
+    fn check(&mut self) -> Result<(), Duration> {
+        if self.state == CircuitState::Open {
+            let elapsed = self.opened_at.map(|opened_at| opened_at.elapsed()).unwrap_or_default();
+            if elapsed < self.reset_after {
+                return Err(self.reset_after - elapsed);
+            }
+            self.state = CircuitState::HalfOpen;
+        }
+        Ok(())
+    }
+
+    /// Returns true if this success closed a previously open circuit.
+    fn record_success(&mut self) -> bool {
+        let recovered = self.state != CircuitState::Closed;
+        self.state = CircuitState::Closed;
+        self.opened_at = None;
+        self.consecutive_failures = 0;
+        self.last_success = Some(Utc::now());
+        recovered
+    }
+
+    /// Returns true if this failure opened the circuit.
+    fn record_failure(&mut self, error: &AttemptError) -> bool {
+        self.consecutive_failures += 1;
+        self.last_failure = Some(Utc::now());
+        self.last_error = Some(error.to_string());
+
+        let trip = match self.state {
+            CircuitState::Closed => self.consecutive_failures >= self.threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if trip {
+            self.state = CircuitState::Open;
+            self.opened_at = Some(Instant::now());
+        }
+        trip
+    }
+
+    /// A rejected request means the endpoint is up, so it does not count towards tripping.
+    fn record_rejection(&mut self, error: &AttemptError) {
+        self.state = CircuitState::Closed;
+        self.opened_at = None;
+        self.consecutive_failures = 0;
+        self.last_failure = Some(Utc::now());
+        self.last_error = Some(error.to_string());
+    }
+}
+
+struct Endpoint {
+    config: EndpointConfig,
+    notifier: Box<dyn Notifier>,
+    breaker: Mutex<CircuitBreaker>,
+}
+
+/// Outcome of the most recent delivery attempt to any endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationResult {
+    pub endpoint: String,
+    pub status: StatusEvent,
+    pub timestamp: DateTime<Utc>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Delivers events to the configured endpoints, whatever their kind, with retries and a
+/// circuit breaker per endpoint.
+pub struct Delivery {
+    endpoints: Vec<Endpoint>,
+    last_result: Mutex<Option<NotificationResult>>,
+}
+
+impl Delivery {
+    pub fn new(endpoints: Vec<EndpointConfig>, device_id: &str) -> Result<Self> {
+        let client = Client::builder()
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        for endpoint in endpoints.iter().filter(|endpoint| !endpoint.enabled) {
+            info!("Endpoint '{}' is disabled and will not be notified", endpoint.name);
+        }
+
+        let endpoints = endpoints
+            .into_iter()
+            .map(|config| {
+                Ok(Endpoint {
+                    notifier: notifier::create_notifier(&config, client.clone(), device_id)?,
+                    breaker: Mutex::new(CircuitBreaker::new(
+                        config.circuit_breaker_threshold,
+                        Duration::from_secs(config.circuit_breaker_reset_secs),
+                    )),
+                    config,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            endpoints,
+            last_result: Mutex::new(None),
+        })
+    }
+
+    /// Names of the endpoints that should receive notifications.
+    pub fn enabled_endpoints(&self) -> Vec<String> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.config.enabled)
+            .map(|endpoint| endpoint.config.name.clone())
+            .collect()
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.config.enabled)
+            .map(|endpoint| {
+                let breaker = endpoint.breaker.lock().unwrap();
+                EndpointHealth {
+                    name: endpoint.config.name.clone(),
+                    kind: endpoint.config.kind.clone(),
+                    circuit: breaker.state,
+                    consecutive_failures: breaker.consecutive_failures,
+                    last_success: breaker.last_success,
+                    last_failure: breaker.last_failure,
+                    last_error: breaker.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn last_result(&self) -> Option<NotificationResult> {
+        self.last_result.lock().unwrap().clone()
+    }
+
+    /// Sends an event to the endpoint, retrying with backoff. Gives up with
+    /// [`NotifyError::ShuttingDown`] when `shutdown` is set while waiting to retry.
+    pub async fn notify_endpoint(
+        &self,
+        name: &str,
+        entry: &OutboxEntry,
+        shutdown: watch::Receiver<bool>,
+    ) -> Result<(), NotifyError> {
+        let endpoint = self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.config.name == name)
+            .ok_or_else(|| NotifyError::UnknownEndpoint(name.to_string()))?;
+
+        let result = self.deliver(endpoint, entry, shutdown).await;
+
+        // An open circuit or a shutdown means nothing new was learned, so there is no result to report
+        if !matches!(result, Err(NotifyError::CircuitOpen(_) | NotifyError::ShuttingDown)) {
+            *self.last_result.lock().unwrap() = Some(NotificationResult {
+                endpoint: name.to_string(),
+                status: entry.event,
+                timestamp: Utc::now(),
+                success: result.is_ok(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            });
+        }
+
+        result
+    }
+
+    async fn deliver(
+        &self,
+        endpoint: &Endpoint,
+        entry: &OutboxEntry,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<(), NotifyError> {
+        let config = &endpoint.config;
+        let event = entry.event;
+
+        let mut attempt = 0;
+        loop {
+            if let Err(retry_in) = endpoint.breaker.lock().unwrap().check() {
+                return Err(NotifyError::CircuitOpen(retry_in));
+            }
+            attempt += 1;
+
+            match endpoint.notifier.send(entry).await {
+                Ok(()) => {
+                    if endpoint.breaker.lock().unwrap().record_success() {
+                        info!("Circuit breaker for endpoint '{}' closed, endpoint recovered", config.name);
+                    }
+                    info!("Endpoint '{}' notified of {} state", config.name, event);
+                    return Ok(());
+                }
+                Err(e) if e.is_permanent() => {
+                    endpoint.breaker.lock().unwrap().record_rejection(&e);
+                    error!("Endpoint '{}' rejected {} event, not retrying: {}", config.name, event, e);
+                    return Err(NotifyError::Rejected(e));
+                }
+                Err(e) => {
+                    if endpoint.breaker.lock().unwrap().record_failure(&e) {
+                        warn!(
+                            "Circuit breaker for endpoint '{}' opened, pausing requests for {}s",
+                            config.name,
+                            config.circuit_breaker_reset_secs
+                        );
+                    }
+
+                    if attempt >= config.retry_attempts {
+                        error!("Failed to notify endpoint '{}' after {} attempts: {}", config.name, attempt, e);
+                        return Err(NotifyError::Exhausted { attempts: attempt, last_error: e });
+                    }
+
+                    let delay = Self::backoff_delay(config, attempt, e.retry_after());
+                    warn!(
+                        "Endpoint '{}' attempt {} failed: {}. Retrying in {}ms...",
+                        config.name,
+                        attempt,
+                        e,
+                        delay.as_millis()
+                    );
+                    // The event stays in the outbox, so there is no need to hold up shutdown
+                    tokio::select! {
+                        _ = sleep(delay) => {}
+                        _ = shutdown.changed() => return Err(NotifyError::ShuttingDown),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Exponential backoff with equal jitter, capped at the endpoint's max delay. A
+    /// `Retry-After` from the endpoint is honoured when it asks for a longer wait.
+    fn backoff_delay(config: &EndpointConfig, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let exponential = config.backoff_initial_ms
+            .saturating_mul(1u64 << (attempt - 1).min(32))
+            .min(config.backoff_max_ms);
+        let jittered = exponential / 2 + rand::rng().random_range(0..=exponential / 2);
+        let delay = Duration::from_millis(jittered);
+
+        match retry_after {
+            Some(retry_after) => retry_after.max(delay),
+            None => delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn unavailable() -> AttemptError {
+        AttemptError::Status {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            body: String::new(),
+            retry_after: None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn breaker_opens_and_recovers_through_half_open() {
+        let reset_after = Duration::from_secs(60);
+        let mut breaker = CircuitBreaker::new(3, reset_after);
+
+        assert!(!breaker.record_failure(&unavailable()));
+        assert!(!breaker.record_failure(&unavailable()));
+        assert_eq!(breaker.check(), Ok(()));
+        assert!(breaker.record_failure(&unavailable()));
+        assert_eq!(breaker.state, CircuitState::Open);
+        assert_eq!(breaker.check(), Err(reset_after));
+
+        tokio::time::advance(Duration::from_secs(45)).await;
+        assert_eq!(breaker.check(), Err(Duration::from_secs(15)));
+
+        // One trial request, and failing it opens the circuit again straight away
+        tokio::time::advance(Duration::from_secs(15)).await;
+        assert_eq!(breaker.check(), Ok(()));
+        assert_eq!(breaker.state, CircuitState::HalfOpen);
+        assert!(breaker.record_failure(&unavailable()));
+        assert_eq!(breaker.check(), Err(reset_after));
+
+        tokio::time::advance(reset_after).await;
+        assert_eq!(breaker.check(), Ok(()));
+        assert!(breaker.record_success());
+        assert_eq!(breaker.state, CircuitState::Closed);
+        assert_eq!(breaker.consecutive_failures, 0);
+        assert!(!breaker.record_success());
+    }
+
+    #[test]
+    fn rejection_does_not_count_towards_tripping() {
+        let mut breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        assert!(!breaker.record_failure(&unavailable()));
+        breaker.record_rejection(&unavailable());
+        assert!(!breaker.record_failure(&unavailable()));
+        assert_eq!(breaker.state, CircuitState::Closed);
+        assert!(breaker.last_error.is_some());
+    }
+
+    fn backoff_config() -> EndpointConfig {
+        EndpointConfig {
+            backoff_initial_ms: 1000,
+            backoff_max_ms: 5000,
+            ..EndpointConfig::default()
+        }
+    }
+
+    /// The smallest and largest of many backoff delays for `attempt`, in milliseconds.
+    fn delay_range(attempt: u32, retry_after: Option<Duration>) -> (u128, u128) {
+        let delays: Vec<u128> = (0..200)
+            .map(|_| Delivery::backoff_delay(&backoff_config(), attempt, retry_after).as_millis())
+            .collect();
+        (*delays.iter().min().unwrap(), *delays.iter().max().unwrap())
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_cap() {
+        for (attempt, low, high) in [(1, 500, 1000), (2, 1000, 2000), (3, 2000, 4000), (4, 2500, 5000), (64, 2500, 5000)] {
+            let (min, max) = delay_range(attempt, None);
+            assert!(low <= min && max <= high, "attempt {}: {}..={}", attempt, min, max);
+            // Jitter actually spreads the delays
+            assert!(min < max, "attempt {}", attempt);
+        }
+    }
+
+    #[test]
+    fn longer_retry_after_takes_precedence() {
+        assert_eq!(delay_range(4, Some(Duration::from_secs(30))), (30_000, 30_000));
+
+        // A shorter one still waits out the backoff
+        let (min, max) = delay_range(2, Some(Duration::from_millis(10)));
+        assert!(1000 <= min && max <= 2000, "{}..={}", min, max);
+    }
+}
@@ -0,0 +1,399 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    StatusEvent,
+    config::EndpointConfig,
+    notifier::{self, AttemptError, Notifier, check_response},
+    outbox::{OutboxEntry, write_atomically},
+    template,
+};
+
+const OPEN_COLOUR: u32 = 0x2ecc71;
+const CLOSED_COLOUR: u32 = 0xe74c3c;
+
+/// The message being edited, remembered across restarts.
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageState {
+    message_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatedMessage {
+    id: String,
+}
+
+/// Posts an embed to a Discord webhook, with `message_template` as its title.
+///
+/// With `edit_message`, one message is edited in place instead, so it can be pinned as a live
+/// status. The ID of that message is kept in the state file, which takes precedence over a
+/// configured `message_id` once a replacement has been posted.
+pub struct DiscordNotifier {
+    client: Client,
+    webhook_url: Url,
+    username: Option<String>,
+    avatar_url: Option<String>,
+    content_template: String,
+    title_template: String,
+    description_template: String,
+    open_image_url: Option<String>,
+    closed_image_url: Option<String>,
+    edit_message: bool,
+    state_path: PathBuf,
+    message_id: Mutex<Option<String>>,
+    device_id: String,
+    timeout: Duration,
+}
+
+impl DiscordNotifier {
+    pub fn new(config: &EndpointConfig, client: Client, device_id: &str) -> Result<Self> {
+        let webhook_url = Url::parse(&config.url)
+            .map_err(|e| anyhow::anyhow!("Invalid Discord webhook URL for endpoint '{}': {}", config.name, e))?;
+        if webhook_url.cannot_be_a_base() {
+            return Err(anyhow::anyhow!("Invalid Discord webhook URL for endpoint '{}'", config.name));
+        }
+
+        let state_path = PathBuf::from(config.discord_state_path());
+        if let Some(dir) = state_path.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create Discord state directory {}", dir.display()))?;
+        }
+
+        // A saved ID is a replacement for a configured message that was deleted, so it wins
+        let message_id = if config.edit_message {
+            load_message_id(&state_path)?.or_else(|| config.message_id.clone())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            client,
+            webhook_url,
+            username: config.username.clone(),
+            avatar_url: config.avatar_url.clone(),
+            content_template: config.content_template.clone(),
+            title_template: config.message_template.clone(),
+            description_template: config.description_template.clone(),
+            open_image_url: config.open_image_url.clone(),
+            closed_image_url: config.closed_image_url.clone(),
+            edit_message: config.edit_message,
+            state_path,
+            message_id: Mutex::new(message_id),
+            device_id: device_id.to_string(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    fn payload(&self, entry: &OutboxEntry) -> serde_json::Value {
+        let variables = notifier::variables(entry, &self.device_id);
+
+        let (colour, image_url) = match entry.event {
+            StatusEvent::Open => (OPEN_COLOUR, &self.open_image_url),
+            StatusEvent::Closed => (CLOSED_COLOUR, &self.closed_image_url),
+        };
+
+        let mut embed = json!({
+            "title": template::render(&self.title_template, &variables),
+            "color": colour,
+            "timestamp": entry.timestamp.to_rfc3339(),
+        });
+        // Without a previous state there is nothing to say how long it lasted
+        if entry.previous.is_some() && !self.description_template.is_empty() {
+            embed["description"] = json!(template::render(&self.description_template, &variables));
+        }
+        if let Some(image_url) = image_url {
+            embed["image"] = json!({ "url": image_url });
+        }
+
+        let mut payload = json!({
+            "content": template::render(&self.content_template, &variables),
+            "embeds": [embed],
+        });
+        if let Some(username) = &self.username {
+            payload["username"] = json!(username);
+        }
+        if let Some(avatar_url) = &self.avatar_url {
+            payload["avatar_url"] = json!(avatar_url);
+        }
+
+        payload
+    }
+
+    /// Edits the current message, returning false when it no longer exists.
+    async fn edit(&self, message_id: &str, payload: &serde_json::Value) -> Result<bool, AttemptError> {
+        let mut url = self.webhook_url.clone();
+        url.path_segments_mut()
+            .expect("webhook URL is checked on creation")
+            .extend(["messages", message_id]);
+
+        let response = self.client
+            .patch(url)
+            .json(payload)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(without_url)?;
+
+        match check_response(response).await {
+            Ok(_) => Ok(true),
+            Err(AttemptError::Status { status: StatusCode::NOT_FOUND, .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Posts a new message, returning its ID.
+    async fn post(&self, payload: &serde_json::Value) -> Result<String, AttemptError> {
+        // wait=true makes Discord return the message, so its ID can be kept for the next edit
+        let mut url = self.webhook_url.clone();
+        url.query_pairs_mut().append_pair("wait", "true");
+
+        let response = self.client
+            .post(url)
+            .json(payload)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(without_url)?;
+        let message: CreatedMessage = check_response(response).await?.json().await.map_err(without_url)?;
+        Ok(message.id)
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    async fn send(&self, entry: &OutboxEntry) -> Result<(), AttemptError> {
+        let payload = self.payload(entry);
+
+        if !self.edit_message {
+            self.post(&payload).await?;
+            return Ok(());
+        }
+
+        let message_id = self.message_id.lock().unwrap().clone();
+        if let Some(message_id) = message_id {
+            if self.edit(&message_id, &payload).await? {
+                return Ok(());
+            }
+            warn!("Discord message {} no longer exists, posting a new one", message_id);
+        }
+
+        let message_id = self.post(&payload).await?;
+        info!("Posted Discord status message {}, editing it from now on", message_id);
+        if let Err(e) = save_message_id(&self.state_path, &message_id) {
+            error!("Failed to save Discord message ID: {}", e);
+        }
+        *self.message_id.lock().unwrap() = Some(message_id);
+        Ok(())
+    }
+}
+
+// The webhook URL contains its token, so keep it out of the logs
+fn without_url(e: reqwest::Error) -> AttemptError {
+    AttemptError::Transport(e.without_url())
+}
+
+fn load_message_id(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let state: MessageState = serde_json::from_str(&contents)
+                .context(format!("Failed to parse Discord state {}", path.display()))?;
+            Ok(Some(state.message_id))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("Failed to read Discord state {}", path.display())),
+    }
+}
+
+fn save_message_id(path: &Path, message_id: &str) -> Result<()> {
+    let state = MessageState {
+        message_id: message_id.to_string(),
+    };
+    write_atomically(path, serde_json::to_string_pretty(&state)?.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use axum::{Json, Router, extract::Path as UrlPath, http::StatusCode as HttpStatus, routing::{patch, post}};
+    use chrono::{TimeZone, Utc};
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    use crate::state_store::LastState;
+    use StatusEvent::{Closed, Open};
+
+    fn config(dir: &TempDir, url: &str) -> EndpointConfig {
+        EndpointConfig {
+            name: "discord".to_string(),
+            kind: "discord".to_string(),
+            url: url.to_string(),
+            state_path: Some(dir.path().join("discord.json").display().to_string()),
+            ..EndpointConfig::default()
+        }
+    }
+
+    fn notifier(config: &EndpointConfig) -> DiscordNotifier {
+        // Straight to the fake webhook, whatever proxy the environment has
+        let client = Client::builder().no_proxy().build().unwrap();
+        DiscordNotifier::new(config, client, "pi").unwrap()
+    }
+
+    fn entry(event: StatusEvent, previous: Option<StatusEvent>) -> OutboxEntry {
+        let timestamp = Utc.with_ymd_and_hms(2025, 6, 2, 18, 0, 0).unwrap();
+        OutboxEntry {
+            sequence: 7,
+            id: Uuid::nil(),
+            timestamp,
+            event,
+            reconciliation: false,
+            previous: previous.map(|status| LastState {
+                status,
+                timestamp: timestamp - chrono::TimeDelta::minutes(192),
+            }),
+        }
+    }
+
+    #[test]
+    fn builds_embed() {
+        let dir = TempDir::new().unwrap();
+        let config = EndpointConfig {
+            username: Some("Doorbot".to_string()),
+            content_template: "#{{sequence}} from {{device_id}}".to_string(),
+            open_image_url: Some("https://example.com/open.png".to_string()),
+            ..config(&dir, "https://discord.example/api/webhooks/1/token")
+        };
+
+        let payload = notifier(&config).payload(&entry(Open, Some(Closed)));
+        assert_eq!(payload, json!({
+            "content": "#7 from pi",
+            "username": "Doorbot",
+            "embeds": [{
+                "title": "The space is open",
+                "description": "It was closed for 3h 12m.",
+                "color": OPEN_COLOUR,
+                "timestamp": "2025-06-02T18:00:00+00:00",
+                "image": { "url": "https://example.com/open.png" },
+            }],
+        }));
+    }
+
+    #[test]
+    fn leaves_out_description_without_previous_state() {
+        let dir = TempDir::new().unwrap();
+        let config = EndpointConfig {
+            open_image_url: Some("https://example.com/open.png".to_string()),
+            ..config(&dir, "https://discord.example/api/webhooks/1/token")
+        };
+
+        let payload = notifier(&config).payload(&entry(Closed, None));
+        let embed = &payload["embeds"][0];
+        assert_eq!(embed["title"], "The space is closed");
+        assert_eq!(embed["color"], CLOSED_COLOUR);
+        assert!(embed.get("description").is_none());
+        assert!(embed.get("image").is_none());
+        assert!(payload.get("username").is_none());
+    }
+
+    /// A webhook that knows every message except `gone`, and fails to edit `broken`. Returns
+    /// its URL and the requests it received.
+    async fn fake_webhook() -> (String, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let posted = requests.clone();
+        let edited = requests.clone();
+        let app = Router::new()
+            .route("/api/webhooks/1/token", post(move || async move {
+                posted.lock().unwrap().push("post".to_string());
+                Json(json!({ "id": "new" }))
+            }))
+            .route("/api/webhooks/1/token/messages/{id}", patch(move |UrlPath(id): UrlPath<String>| async move {
+                edited.lock().unwrap().push(format!("edit {}", id));
+                match id.as_str() {
+                    "gone" => HttpStatus::NOT_FOUND,
+                    "broken" => HttpStatus::INTERNAL_SERVER_ERROR,
+                    _ => HttpStatus::OK,
+                }
+            }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/webhooks/1/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, requests)
+    }
+
+    fn requests(requests: &Mutex<Vec<String>>) -> Vec<String> {
+        std::mem::take(&mut *requests.lock().unwrap())
+    }
+
+    #[tokio::test]
+    async fn posts_every_change_without_edit_mode() {
+        let dir = TempDir::new().unwrap();
+        let (url, received) = fake_webhook().await;
+        let notifier = notifier(&config(&dir, &url));
+
+        notifier.send(&entry(Open, None)).await.unwrap();
+        notifier.send(&entry(Closed, Some(Open))).await.unwrap();
+        assert_eq!(requests(&received), ["post", "post"]);
+        assert!(!dir.path().join("discord.json").exists());
+    }
+
+    #[tokio::test]
+    async fn edits_configured_message() {
+        let dir = TempDir::new().unwrap();
+        let (url, received) = fake_webhook().await;
+        let config = EndpointConfig {
+            edit_message: true,
+            message_id: Some("pinned".to_string()),
+            ..config(&dir, &url)
+        };
+
+        notifier(&config).send(&entry(Open, None)).await.unwrap();
+        assert_eq!(requests(&received), ["edit pinned"]);
+        assert!(!dir.path().join("discord.json").exists());
+    }
+
+    #[tokio::test]
+    async fn posts_replacement_for_deleted_message() {
+        let dir = TempDir::new().unwrap();
+        let (url, received) = fake_webhook().await;
+        let config = EndpointConfig {
+            edit_message: true,
+            message_id: Some("gone".to_string()),
+            ..config(&dir, &url)
+        };
+
+        let first = notifier(&config);
+        first.send(&entry(Open, None)).await.unwrap();
+        first.send(&entry(Closed, Some(Open))).await.unwrap();
+        assert_eq!(requests(&received), ["edit gone", "post", "edit new"]);
+
+        // The saved replacement wins over the configured message after a restart
+        notifier(&config).send(&entry(Open, Some(Closed))).await.unwrap();
+        assert_eq!(requests(&received), ["edit new"]);
+    }
+
+    #[tokio::test]
+    async fn failed_edit_does_not_post() {
+        let dir = TempDir::new().unwrap();
+        let (url, received) = fake_webhook().await;
+        let config = EndpointConfig {
+            edit_message: true,
+            message_id: Some("broken".to_string()),
+            ..config(&dir, &url)
+        };
+
+        let result = notifier(&config).send(&entry(Open, None)).await;
+        assert!(matches!(result, Err(AttemptError::Status { status: StatusCode::INTERNAL_SERVER_ERROR, .. })));
+        assert_eq!(requests(&received), ["edit broken"]);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info};
+
+use crate::{
+    StatusEvent,
+    history::History,
+    outbox::{Outbox, run_blocking},
+    state_store::{LastState, StateStore},
+    status::StatusTracker,
+};
+
+/// State changes that can wait for the dispatcher before publishing blocks.
+const QUEUE_CAPACITY: usize = 64;
+
+/// A state change from `source`, with the state of every input at the time. Reconciliation
+/// events are the ones sent at startup because the input changed while noisebell was not running.
+struct Publication {
+    status: StatusEvent,
+    source: String,
+    reconciliation: bool,
+    inputs: BTreeMap<String, StatusEvent>,
+}
+
+/// Hands state changes to the [`Dispatcher`]. Cheap to clone, one per source.
+#[derive(Clone)]
+pub struct Publisher {
+    sender: mpsc::Sender<Publication>,
+}
+
+impl Publisher {
+    /// Queues a state change. Waits while the queue is full, so a flapping input cannot use up
+    /// memory faster than events are written to the outbox.
+    pub async fn publish(
+        &self,
+        status: StatusEvent,
+        source: &str,
+        reconciliation: bool,
+        inputs: BTreeMap<String, StatusEvent>,
+    ) {
+        let publication = Publication {
+            status,
+            source: source.to_string(),
+            reconciliation,
+            inputs,
+        };
+        if self.sender.send(publication).await.is_err() {
+            error!("Dispatcher has stopped, dropping {} event", status);
+        }
+    }
+}
+
+/// The single place state changes are published from.
+///
+/// Changes from every input are taken one at a time in the order they were published, written
+/// to the outbox and history, and then announced to the other sinks. The outbox workers deliver
+/// them to each endpoint in that same order.
+pub struct Dispatcher {
+    receiver: mpsc::Receiver<Publication>,
+    storage: Storage,
+    tracker: Arc<StatusTracker>,
+    /// The state being replaced, for the previous status and duration in notifications.
+    previous: Option<LastState>,
+}
+
+/// Creates the dispatcher and the publisher feeding it. `last_state` is the state from before
+/// the restart, if known.
+pub fn channel(
+    outbox: Arc<Outbox>,
+    history: Arc<History>,
+    state_store: Arc<StateStore>,
+    tracker: Arc<StatusTracker>,
+    last_state: Option<LastState>,
+) -> (Publisher, Dispatcher) {
+    let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+    let dispatcher = Dispatcher {
+        receiver,
+        storage: Storage {
+            outbox,
+            history,
+            state_store,
+        },
+        tracker,
+        previous: last_state,
+    };
+    (Publisher { sender }, dispatcher)
+}
+
+impl Dispatcher {
+    /// Runs until every publisher is gone or shutdown is requested. Changes published before the
+    /// shutdown still make it into the outbox.
+    pub async fn run(mut self, mut shutdown: watch::Receiver<bool>) {
+        loop {
+            tokio::select! {
+                biased;
+                publication = self.receiver.recv() => match publication {
+                    Some(publication) => self.dispatch(publication).await,
+                    None => return,
+                },
+                _ = shutdown.changed() => {
+                    while let Ok(publication) = self.receiver.try_recv() {
+                        self.dispatch(publication).await;
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn dispatch(&mut self, publication: Publication) {
+        let status = publication.status;
+        let (storage, previous) = (self.storage.clone(), self.previous);
+
+        // Waiting for the write before taking the next change keeps the events in order
+        let last = run_blocking(move || storage.write(publication, previous)).await;
+        self.previous = Some(last);
+        self.tracker.record(status);
+    }
+}
+
+/// Where published changes are written to, all of which is blocking file work.
+#[derive(Clone)]
+struct Storage {
+    outbox: Arc<Outbox>,
+    history: Arc<History>,
+    state_store: Arc<StateStore>,
+}
+
+impl Storage {
+    /// Writes a change to the outbox, history and state store, and returns it as the new last
+    /// state.
+    fn write(&self, publication: Publication, previous: Option<LastState>) -> LastState {
+        let Publication { status, source, reconciliation, inputs } = publication;
+
+        let timestamp = match self.outbox.append(status, reconciliation, previous) {
+            Ok(entry) => {
+                info!("Queued event #{} ({}) for delivery", entry.sequence, status);
+                if let Err(e) = self.history.record_event(&entry, &source, &inputs) {
+                    error!("Failed to record {} event in history: {}", status, e);
+                }
+                entry.timestamp
+            }
+            Err(e) => {
+                error!("Failed to queue {} event: {}", status, e);
+                Utc::now()
+            }
+        };
+
+        let last = LastState { status, timestamp };
+        if let Err(e) = self.state_store.save(&last) {
+            error!("Failed to save last known state: {}", e);
+        }
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    use StatusEvent::{Closed, Open};
+
+    struct Setup {
+        history: Arc<History>,
+        state_store: Arc<StateStore>,
+        tracker: Arc<StatusTracker>,
+        publisher: Publisher,
+        dispatcher: Dispatcher,
+    }
+
+    fn setup(dir: &TempDir) -> Setup {
+        let history = Arc::new(History::open(dir.path().join("history.jsonl")).unwrap());
+        let outbox = Outbox::open(dir.path().join("outbox.jsonl"), vec!["a".to_string()], 1000, history.clone()).unwrap();
+        let state_store = Arc::new(StateStore::open(dir.path().join("state.json")).unwrap());
+        let tracker = Arc::new(StatusTracker::new());
+        let (publisher, dispatcher) = channel(Arc::new(outbox), history.clone(), state_store.clone(), tracker.clone(), None);
+        Setup {
+            history,
+            state_store,
+            tracker,
+            publisher,
+            dispatcher,
+        }
+    }
+
+    async fn publish(publisher: &Publisher, status: StatusEvent, source: &str) {
+        let inputs = BTreeMap::from([(source.to_string(), status)]);
+        publisher.publish(status, source, false, inputs).await;
+    }
+
+    fn recorded(history: &History) -> Vec<(u64, StatusEvent, String)> {
+        let events = history.query(None, None).unwrap();
+        events.into_iter().map(|event| (event.sequence, event.status, event.source)).collect()
+    }
+
+    #[tokio::test]
+    async fn writes_changes_in_publish_order() {
+        let dir = TempDir::new().unwrap();
+        let Setup { history, state_store, tracker, publisher, dispatcher } = setup(&dir);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let bolt = publisher.clone();
+        publish(&publisher, Open, "door").await;
+        publish(&bolt, Closed, "bolt").await;
+        publish(&publisher, Open, "door").await;
+        drop((publisher, bolt));
+        dispatcher.run(shutdown_rx).await;
+
+        assert_eq!(
+            recorded(&history),
+            vec![(1, Open, "door".to_string()), (2, Closed, "bolt".to_string()), (3, Open, "door".to_string())]
+        );
+        assert_eq!(state_store.load().unwrap().map(|last| last.status), Some(Open));
+        assert_eq!(tracker.current(), Some(Open));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publish_waits_while_queue_is_full() {
+        let dir = TempDir::new().unwrap();
+        let Setup { history, publisher, dispatcher, .. } = setup(&dir);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        for _ in 0..QUEUE_CAPACITY {
+            publish(&publisher, Open, "door").await;
+        }
+        let blocked = tokio::time::timeout(Duration::from_secs(1), publish(&publisher, Closed, "door")).await;
+        assert!(blocked.is_err());
+
+        let dispatcher = tokio::spawn(dispatcher.run(shutdown_rx));
+        publish(&publisher, Closed, "door").await;
+        drop(publisher);
+        dispatcher.await.unwrap();
+
+        let events = recorded(&history);
+        assert_eq!(events.len(), QUEUE_CAPACITY + 1);
+        assert_eq!(events.last().unwrap().1, Closed);
+    }
+
+    #[tokio::test]
+    async fn shutdown_writes_queued_changes() {
+        let dir = TempDir::new().unwrap();
+        let Setup { history, publisher, dispatcher, .. } = setup(&dir);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        publish(&publisher, Open, "door").await;
+        publish(&publisher, Closed, "door").await;
+        shutdown_tx.send(true).unwrap();
+
+        // Stops even though a publisher is still around
+        tokio::time::timeout(Duration::from_secs(5), dispatcher.run(shutdown_rx)).await.unwrap();
+        assert_eq!(recorded(&history), vec![(1, Open, "door".to_string()), (2, Closed, "door".to_string())]);
+
+        // Later changes are dropped instead of waiting forever
+        publish(&publisher, Open, "door").await;
+        assert_eq!(recorded(&history).len(), 2);
+    }
+}
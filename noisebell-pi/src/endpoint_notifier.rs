@@ -1,95 +1,202 @@
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use tracing::{info, error, warn};
-use reqwest::Client;
-use tokio::time::{sleep, Duration};
+use std::time::Duration;
 
-use crate::StatusEvent;
 use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{
+    Client, Method,
+    header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue},
+};
+use noisebell::signing;
+use tracing::warn;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EndpointConfig {
-    pub url: String,
-    pub api_key: Option<String>,
-    pub timeout_secs: u64,
-    pub retry_attempts: u32,
+use crate::{
+    config::EndpointConfig,
+    notifier::{self, AttemptError, Notifier, check_response},
+    outbox::OutboxEntry,
+    template,
+};
+
+/// How the payload template is encoded, which also decides how variables are escaped.
+#[derive(Debug, Clone, Copy)]
+enum PayloadFormat {
+    Json,
+    Form,
+    Text,
+}
+
+impl PayloadFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            PayloadFormat::Json => "application/json",
+            PayloadFormat::Form => "application/x-www-form-urlencoded",
+            PayloadFormat::Text => "text/plain; charset=utf-8",
+        }
+    }
 }
 
+/// Sends the rendered payload template to an HTTP endpoint, with an optional Bearer token and
+/// any extra headers.
 pub struct EndpointNotifier {
-    config: EndpointConfig,
     client: Client,
+    url: String,
+    method: Method,
+    format: PayloadFormat,
+    payload_template: String,
+    /// Header values are templates too, rendered without escaping.
+    headers: Vec<(HeaderName, String)>,
+    api_key: Option<String>,
+    signing_secret: Option<String>,
+    device_id: String,
+    timeout: Duration,
 }
 
 impl EndpointNotifier {
-    pub fn new(config: EndpointConfig) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(config.timeout_secs))
-            .build()
-            .expect("Failed to create HTTP client");
-        
-        Self { config, client }
-    }
+    pub fn new(config: &EndpointConfig, client: Client, device_id: &str) -> Result<Self> {
+        let method = Method::from_bytes(config.method.to_uppercase().as_bytes())
+            .map_err(|_| anyhow::anyhow!("Invalid HTTP method for endpoint '{}': {}", config.name, config.method))?;
 
-    pub async fn notify_endpoint(&self, event: StatusEvent) -> Result<()> {
-        let status = match event {
-            StatusEvent::Open => "open",
-            StatusEvent::Closed => "closed",
+        let format = match config.format.as_str() {
+            "json" => PayloadFormat::Json,
+            "form" => PayloadFormat::Form,
+            "text" => PayloadFormat::Text,
+            format => return Err(anyhow::anyhow!("Unknown payload format for endpoint '{}': {}", config.name, format)),
         };
-        
-        let payload = json!({
-            "status": status,
-        });
-
-        let mut success = false;
-        let mut last_error = None;
-        
-        for attempt in 1..=self.config.retry_attempts {
-            match self.send_request(&payload).await {
-                Ok(_) => {
-                    success = true;
-                    break;
-                }
-                Err(e) => {
-                    last_error = Some(e);
-                    if attempt < self.config.retry_attempts {
-                        warn!("Attempt {} failed: {}. Retrying...", attempt, last_error.as_ref().unwrap());
-                        sleep(Duration::from_secs(1)).await;
-                    }
-                }
-            }
-        }
 
-        if !success {
-            let error_msg = last_error.unwrap_or_else(|| anyhow::anyhow!("Unknown error"));
-            error!("Failed to notify endpoint after {} attempts: {}", self.config.retry_attempts, error_msg);
-            return Err(error_msg);
+        let headers = config.headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| anyhow::anyhow!("Invalid header name for endpoint '{}': {}", config.name, name))?;
+                Ok((name, value.clone()))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            method,
+            format,
+            payload_template: config.payload_template.clone(),
+            headers,
+            api_key: config.api_key.clone(),
+            signing_secret: config.signing_secret.clone(),
+            device_id: device_id.to_string(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    fn payload(&self, variables: &[(&str, String)]) -> String {
+        match self.format {
+            PayloadFormat::Json => template::render_escaped(&self.payload_template, variables, template::json_escape),
+            PayloadFormat::Form => template::render_escaped(&self.payload_template, variables, template::form_escape),
+            PayloadFormat::Text => template::render(&self.payload_template, variables),
         }
+    }
 
-        Ok(())
+    fn headers(&self, variables: &[(&str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            match HeaderValue::from_str(&template::render(value, variables)) {
+                Ok(value) => {
+                    headers.insert(name, value);
+                }
+                Err(_) => warn!("Skipping header {} with an invalid value", name),
+            }
+        }
+        headers
     }
+}
+
+#[async_trait]
+impl Notifier for EndpointNotifier {
+    async fn send(&self, entry: &OutboxEntry) -> Result<(), AttemptError> {
+        let variables = notifier::variables(entry, &self.device_id);
+        let body = self.payload(&variables);
 
-    async fn send_request(&self, payload: &serde_json::Value) -> Result<()> {
         let mut request = self.client
-            .post(&self.config.url)
-            .json(payload);
+            .request(self.method.clone(), &self.url)
+            .header(CONTENT_TYPE, self.format.content_type())
+            .header("Idempotency-Key", entry.id.to_string())
+            .header("X-Noisebell-Sequence", entry.sequence);
 
-        if let Some(api_key) = &self.config.api_key {
+        if let Some(api_key) = &self.api_key {
             request = request.header("Authorization", format!("Bearer {}", api_key));
         }
 
+        // Signed for every attempt, so a retry gets a fresh timestamp and nonce
+        if let Some(secret) = &self.signing_secret {
+            let signature = signing::sign_request(secret.as_bytes(), body.as_bytes());
+            request = request
+                .header(signing::TIMESTAMP_HEADER, signature.timestamp)
+                .header(signing::NONCE_HEADER, signature.nonce)
+                .header(signing::SIGNATURE_HEADER, signature.signature);
+        }
+
+        // Replaces rather than adds, so a configured header can override any of the ones above
+        request = request.headers(self.headers(&variables)).body(body);
+
         let response = request
-            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .timeout(self.timeout)
             .send()
             .await?;
 
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "HTTP request failed with status {}: {}",
-                response.status(),
-                response.text().await.unwrap_or_else(|_| "Unknown error".to_string())
-            ));
-        }
-
+        check_response(response).await?;
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notifier(format: &str, payload_template: &str, headers: &[(&str, &str)]) -> EndpointNotifier {
+        let config = EndpointConfig {
+            format: format.to_string(),
+            payload_template: payload_template.to_string(),
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            ..EndpointConfig::default()
+        };
+        EndpointNotifier::new(&config, Client::new(), "pi").unwrap()
+    }
+
+    fn variables() -> Vec<(&'static str, String)> {
+        vec![("status", "open".to_string()), ("device_id", "say \"hi\" & a=b\\c\nnext".to_string())]
+    }
+
+    #[test]
+    fn json_payload_stays_valid() {
+        let notifier = notifier("json", r#"{"status": "{{status}}", "device": "{{device_id}}"}"#, &[]);
+
+        let payload: serde_json::Value = serde_json::from_str(&notifier.payload(&variables())).unwrap();
+        assert_eq!(payload["status"], "open");
+        assert_eq!(payload["device"], "say \"hi\" & a=b\\c\nnext");
+    }
+
+    #[test]
+    fn form_payload_keeps_fields_apart() {
+        let notifier = notifier("form", "status={{status}}&device={{device_id}}", &[]);
+
+        let payload = notifier.payload(&variables());
+        let fields: Vec<(String, String)> = url::form_urlencoded::parse(payload.as_bytes()).into_owned().collect();
+        assert_eq!(fields, [
+            ("status".to_string(), "open".to_string()),
+            ("device".to_string(), "say \"hi\" & a=b\\c\nnext".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn text_payload_is_not_escaped() {
+        let notifier = notifier("text", "{{device_id}}", &[]);
+        assert_eq!(notifier.payload(&variables()), "say \"hi\" & a=b\\c\nnext");
+    }
+
+    #[test]
+    fn header_values_are_templates() {
+        let notifier = notifier("json", "{}", &[("X-Status", "is {{status}}"), ("X-Device", "{{device_id}}")]);
+
+        let headers = notifier.headers(&variables());
+        assert_eq!(headers["x-status"], "is open");
+        // A newline is not allowed in a header, so that one is skipped rather than sent broken
+        assert!(!headers.contains_key("x-device"));
+    }
+}
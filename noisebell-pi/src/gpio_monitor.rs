@@ -1,45 +1,159 @@
-use std::time::Duration;
-use anyhow::{Result, Context};
-use crate::{StatusEvent, monitor::Monitor};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::{channel::mpsc, stream::{BoxStream, StreamExt}};
+use crate::{
+    StatusEvent,
+    monitor::Monitor,
+    pin::{Edge, InputPin, Level, RppalPin},
+};
 
+/// Reports every edge of the pin. Bounces are filtered later by the debounce stage.
 pub struct GpioMonitor {
-    pin: rppal::gpio::InputPin,
-    debounce_delay: Duration,
+    pin: Box<dyn InputPin>,
 }
 
 impl GpioMonitor {
-    pub fn new(pin_number: u8, debounce_delay: Duration) -> Result<Self> {
-        let gpio = rppal::gpio::Gpio::new().context("Failed to initialize GPIO")?;
-        let pin = gpio
-            .get(pin_number)
-            .context(format!("Failed to get GPIO pin {}", pin_number))?
-            .into_input_pullup();
-
-        Ok(Self {
-            pin,
-            debounce_delay,
-        })
+    pub fn new(pin_number: u8) -> Result<Self> {
+        Ok(Self::with_pin(Box::new(RppalPin::new(pin_number)?)))
+    }
+
+    /// Watches any [`InputPin`], such as a mock in tests.
+    pub fn with_pin(pin: Box<dyn InputPin>) -> Self {
+        Self { pin }
+    }
+}
+
+// The switch connects the pin to ground, against the pull-up, while the space is open
+fn status_for_level(level: Level) -> StatusEvent {
+    match level {
+        Level::Low => StatusEvent::Open,
+        Level::High => StatusEvent::Closed,
     }
 }
 
+fn status_for_edge(edge: Edge) -> StatusEvent {
+    match edge {
+        Edge::Rising => StatusEvent::Closed,
+        Edge::Falling => StatusEvent::Open,
+    }
+}
+
+#[async_trait]
 impl Monitor for GpioMonitor {
-    fn monitor(&mut self, mut callback: Box<dyn FnMut(StatusEvent) + Send>) -> Result<()> {
-        self.pin
-            .set_async_interrupt(rppal::gpio::Trigger::Both, Some(self.debounce_delay), move |event| {
-                match event.trigger {
-                    rppal::gpio::Trigger::RisingEdge => callback(StatusEvent::Closed),
-                    rppal::gpio::Trigger::FallingEdge => callback(StatusEvent::Open),
-                    _ => (), // Ignore other triggers
-                }
-            })?;
-
-        Ok(())
-    }
-
-    fn get_current_state(&self) -> StatusEvent {
-        match self.pin.read() {
-            rppal::gpio::Level::Low => StatusEvent::Open,
-            rppal::gpio::Level::High => StatusEvent::Closed,
+    fn events(&mut self) -> Result<BoxStream<'static, StatusEvent>> {
+        // Pins may call back on their own thread, so hand edges over through a channel
+        let (sender, receiver) = mpsc::unbounded();
+        self.pin.on_edge(Box::new(move |edge| {
+            let _ = sender.unbounded_send(edge);
+        }))?;
+
+        Ok(receiver.map(status_for_edge).boxed())
+    }
+
+    async fn current_state(&self) -> StatusEvent {
+        status_for_level(self.pin.read())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::{debounce, mock_pin::MockPin};
+    use tokio::time::Instant;
+
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn debounced(pin: MockPin) -> BoxStream<'static, StatusEvent> {
+        let initial = status_for_level(pin.read());
+        let settings = debounce::Settings {
+            open_settle: DEBOUNCE,
+            close_settle: DEBOUNCE,
+            min_open: Duration::ZERO,
+            min_closed: Duration::ZERO,
+            flap_max_changes: 0,
+            flap_window: Duration::ZERO,
+        };
+        let mut monitor = GpioMonitor::with_pin(Box::new(pin));
+        debounce::debounce(monitor.events().unwrap(), settings, initial)
+    }
+
+    #[tokio::test]
+    async fn current_state_follows_level() {
+        let monitor = GpioMonitor::with_pin(Box::new(MockPin::new(Level::Low)));
+        assert_eq!(monitor.current_state().await, StatusEvent::Open);
+
+        let monitor = GpioMonitor::with_pin(Box::new(MockPin::new(Level::High)));
+        assert_eq!(monitor.current_state().await, StatusEvent::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn falling_edge_opens_and_rising_edge_closes() {
+        let pin = MockPin::new(Level::High)
+            .then(ms(100), Level::Low)
+            .then(ms(100), Level::High);
+
+        let mut monitor = GpioMonitor::with_pin(Box::new(pin));
+        let events: Vec<_> = monitor.events().unwrap().collect().await;
+        assert_eq!(events, vec![StatusEvent::Open, StatusEvent::Closed]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_bounce_is_reported_raw() {
+        let pin = MockPin::new(Level::High).bounce(ms(100), Level::Low, 2, ms(3));
+
+        let mut monitor = GpioMonitor::with_pin(Box::new(pin));
+        let events: Vec<_> = monitor.events().unwrap().collect().await;
+        assert_eq!(events.len(), 5);
+        assert_eq!(events.last(), Some(&StatusEvent::Open));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounces_are_reported_once() {
+        let pin = MockPin::new(Level::High)
+            .bounce(ms(100), Level::Low, 5, ms(3))
+            .bounce(ms(500), Level::High, 8, ms(1));
+
+        let events: Vec<_> = debounced(pin).collect().await;
+        assert_eq!(events, vec![StatusEvent::Open, StatusEvent::Closed]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn glitches_shorter_than_debounce_are_ignored() {
+        let pin = MockPin::new(Level::High)
+            .then(ms(100), Level::Low)
+            .then(ms(20), Level::High)
+            .then(ms(100), Level::Low)
+            .then(ms(49), Level::High);
+
+        let events: Vec<_> = debounced(pin).collect().await;
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn change_is_reported_after_it_settles() {
+        let pin = MockPin::new(Level::High).bounce(ms(100), Level::Low, 3, ms(10));
+        let start = Instant::now();
+
+        let mut events = debounced(pin);
+        assert_eq!(events.next().await, Some(StatusEvent::Open));
+        // Six bounce edges 10ms apart after the first one, then the debounce delay
+        assert_eq!(start.elapsed(), ms(100 + 60) + DEBOUNCE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn events_keep_their_order() {
+        let mut pin = MockPin::new(Level::High);
+        for _ in 0..10 {
+            pin = pin.bounce(ms(200), Level::Low, 2, ms(5)).bounce(ms(200), Level::High, 2, ms(5));
         }
+
+        let events: Vec<_> = debounced(pin).collect().await;
+        assert_eq!(events.len(), 20);
+        assert!(events.chunks(2).all(|pair| pair == [StatusEvent::Open, StatusEvent::Closed]));
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use gpiocdev::{
+    Request,
+    line::{Bias, EdgeDetection, EdgeKind, Offset, Value},
+    tokio::AsyncRequest,
+};
+use tracing::{error, info};
+
+use crate::{StatusEvent, config::GpiodConfig, monitor::Monitor};
+
+/// Watches a line through the Linux GPIO character device (`/dev/gpiochipN`), so it works on
+/// any board with a GPIO driver, and with the `gpio-sim` and `gpio-mockup` kernel modules.
+///
+/// The kernel reports every edge, so nothing is polled. Bounces are filtered later by the
+/// debounce stage.
+pub struct GpiodMonitor {
+    request: Arc<AsyncRequest>,
+    offset: Offset,
+}
+
+impl GpiodMonitor {
+    pub fn new(config: &GpiodConfig) -> Result<Self> {
+        let bias = match config.bias.as_str() {
+            "pull_up" => Some(Bias::PullUp),
+            "pull_down" => Some(Bias::PullDown),
+            "disabled" => Some(Bias::Disabled),
+            "as_is" => None,
+            bias => return Err(anyhow::anyhow!("Unknown GPIO bias: {}", bias)),
+        };
+
+        // A line is either an offset on the configured chip or a name, such as `GPIO17`, that is
+        // looked up on every chip
+        let (chip, offset) = match config.line.parse::<Offset>() {
+            Ok(offset) => (config.chip.clone().into(), offset),
+            Err(_) => {
+                let found = gpiocdev::find_named_line(&config.line)
+                    .ok_or_else(|| anyhow::anyhow!("No GPIO line named {}", config.line))?;
+                (found.chip, found.info.offset)
+            }
+        };
+
+        let request = Request::builder()
+            .on_chip(&chip)
+            .with_consumer("noisebell")
+            .with_line(offset)
+            .as_input()
+            .with_bias(bias)
+            .with_edge_detection(EdgeDetection::BothEdges)
+            .request()
+            .context(format!("Failed to request GPIO line {} on {}", offset, chip.display()))?;
+
+        info!("Watching GPIO line {} on {}", offset, chip.display());
+
+        Ok(Self {
+            request: Arc::new(AsyncRequest::new(request)),
+            offset,
+        })
+    }
+}
+
+#[async_trait]
+impl Monitor for GpiodMonitor {
+    fn events(&mut self) -> Result<BoxStream<'static, StatusEvent>> {
+        let events = stream::unfold(self.request.clone(), |request| async move {
+            match request.read_edge_event().await {
+                // Same wiring as the gpio monitor: the switch pulls the line low when open
+                Ok(event) => {
+                    let status = match event.kind {
+                        EdgeKind::Rising => StatusEvent::Closed,
+                        EdgeKind::Falling => StatusEvent::Open,
+                    };
+                    Some((status, request))
+                }
+                Err(e) => {
+                    error!("Failed to read GPIO edge event: {}", e);
+                    None
+                }
+            }
+        });
+
+        Ok(events.boxed())
+    }
+
+    async fn current_state(&self) -> StatusEvent {
+        match self.request.as_ref().as_ref().value(self.offset) {
+            Ok(Value::Inactive) => StatusEvent::Open,
+            Ok(Value::Active) => StatusEvent::Closed,
+            Err(e) => {
+                error!("Failed to read GPIO line {}: {}, assuming closed", self.offset, e);
+                StatusEvent::Closed
+            }
+        }
+    }
+}
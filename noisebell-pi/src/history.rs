@@ -0,0 +1,403 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{StatusEvent, outbox::OutboxEntry};
+
+/// One line of the history file.
+///
+/// State changes and delivery outcomes are separate records, so the file is only ever appended
+/// to. They are joined on the outbox sequence number when reading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum HistoryRecord {
+    Event {
+        sequence: u64,
+        id: Uuid,
+        timestamp: DateTime<Utc>,
+        status: StatusEvent,
+        source: String,
+        #[serde(default)]
+        reconciliation: bool,
+        inputs: BTreeMap<String, StatusEvent>,
+    },
+    Delivery {
+        sequence: u64,
+        endpoint: String,
+        timestamp: DateTime<Utc>,
+        outcome: DeliveryOutcome,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryOutcome {
+    Delivered,
+    /// The endpoint permanently rejected the event, so it was dropped for that endpoint.
+    Rejected,
+    /// A newer event was waiting for the endpoint, so this one was skipped.
+    Superseded,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub outcome: DeliveryOutcome,
+    pub timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A recorded state change with what happened to it at each endpoint.
+///
+/// Endpoints missing from `deliveries` have not received the event yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEvent {
+    pub sequence: u64,
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub status: StatusEvent,
+    /// Where the change came from: the input that changed, `combined` for reconciliation
+    /// between several inputs, or `override` for manual overrides.
+    pub source: String,
+    pub reconciliation: bool,
+    /// The state of each input when the change was published.
+    pub inputs: BTreeMap<String, StatusEvent>,
+    pub deliveries: BTreeMap<String, Delivery>,
+}
+
+/// Append-only log of every published state change, kept on disk for querying and export.
+pub struct History {
+    path: PathBuf,
+    /// Serializes appends and reads so a query never sees half a line.
+    file: Mutex<()>,
+}
+
+impl History {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create history directory {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            path,
+            file: Mutex::new(()),
+        })
+    }
+
+    pub fn record_event(&self, entry: &OutboxEntry, source: &str, inputs: &BTreeMap<String, StatusEvent>) -> Result<()> {
+        self.append(&HistoryRecord::Event {
+            sequence: entry.sequence,
+            id: entry.id,
+            timestamp: entry.timestamp,
+            status: entry.event,
+            source: source.to_string(),
+            reconciliation: entry.reconciliation,
+            inputs: inputs.clone(),
+        })
+    }
+
+    pub fn record_delivery(
+        &self,
+        sequence: u64,
+        endpoint: &str,
+        outcome: DeliveryOutcome,
+        error: Option<String>,
+    ) -> Result<()> {
+        self.append(&HistoryRecord::Delivery {
+            sequence,
+            endpoint: endpoint.to_string(),
+            timestamp: Utc::now(),
+            outcome,
+            error,
+        })
+    }
+
+    /// Returns the events in `[from, to)`, oldest first. Either bound may be left open.
+    pub fn query(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<HistoryEvent>> {
+        let mut events: BTreeMap<u64, HistoryEvent> = BTreeMap::new();
+
+        for record in self.read_records()? {
+            match record {
+                HistoryRecord::Event { sequence, id, timestamp, status, source, reconciliation, inputs } => {
+                    if from.is_some_and(|from| timestamp < from) || to.is_some_and(|to| timestamp >= to) {
+                        continue;
+                    }
+                    events.insert(sequence, HistoryEvent {
+                        sequence,
+                        id,
+                        timestamp,
+                        status,
+                        source,
+                        reconciliation,
+                        inputs,
+                        deliveries: BTreeMap::new(),
+                    });
+                }
+                HistoryRecord::Delivery { sequence, endpoint, timestamp, outcome, error } => {
+                    if let Some(event) = events.get_mut(&sequence) {
+                        event.deliveries.insert(endpoint, Delivery { outcome, timestamp, error });
+                    }
+                }
+            }
+        }
+
+        let mut events: Vec<HistoryEvent> = events.into_values().collect();
+        events.sort_by_key(|event| event.timestamp);
+        Ok(events)
+    }
+
+    fn append(&self, record: &HistoryRecord) -> Result<()> {
+        let _guard = self.file.lock().unwrap();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("Failed to open history {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    fn read_records(&self) -> Result<Vec<HistoryRecord>> {
+        let _guard = self.file.lock().unwrap();
+
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(format!("Failed to open history {}", self.path.display())),
+        };
+
+        let mut records = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<HistoryRecord>(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Skipping unreadable history line {} in {}: {}", index + 1, self.path.display(), e),
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+/// Renders events as CSV with one state column per input and one delivery column per endpoint.
+pub fn to_csv(events: &[HistoryEvent]) -> String {
+    let inputs: BTreeSet<&String> = events.iter().flat_map(|event| event.inputs.keys()).collect();
+    let endpoints: BTreeSet<&String> = events.iter().flat_map(|event| event.deliveries.keys()).collect();
+
+    let mut header = vec![
+        "sequence".to_string(),
+        "id".to_string(),
+        "timestamp".to_string(),
+        "status".to_string(),
+        "source".to_string(),
+        "reconciliation".to_string(),
+    ];
+    header.extend(inputs.iter().map(|input| format!("input_{}", input)));
+    header.extend(endpoints.iter().map(|endpoint| format!("delivery_{}", endpoint)));
+
+    let mut csv = String::new();
+    push_csv_row(&mut csv, &header);
+
+    for event in events {
+        let mut row = vec![
+            event.sequence.to_string(),
+            event.id.to_string(),
+            event.timestamp.to_rfc3339(),
+            event.status.to_string(),
+            event.source.clone(),
+            event.reconciliation.to_string(),
+        ];
+        row.extend(inputs.iter().map(|input| {
+            event.inputs.get(*input).map(|status| status.to_string()).unwrap_or_default()
+        }));
+        row.extend(endpoints.iter().map(|endpoint| match event.deliveries.get(*endpoint).map(|delivery| delivery.outcome) {
+            Some(DeliveryOutcome::Delivered) => "delivered".to_string(),
+            Some(DeliveryOutcome::Rejected) => "rejected".to_string(),
+            Some(DeliveryOutcome::Superseded) => "superseded".to_string(),
+            // Not delivered yet, or the endpoint was added after the event
+            None => String::new(),
+        }));
+        push_csv_row(&mut csv, &row);
+    }
+
+    csv
+}
+
+fn push_csv_row(csv: &mut String, fields: &[String]) {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+    csv.push_str(&fields.join(","));
+    csv.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    use StatusEvent::{Closed, Open};
+
+    fn at(hours: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 2, 0, 0, 0).unwrap() + chrono::TimeDelta::hours(hours)
+    }
+
+    fn open(dir: &TempDir) -> History {
+        History::open(dir.path().join("history.jsonl")).unwrap()
+    }
+
+    /// Records events with the given sequence numbers, each at that many hours.
+    fn record(history: &History, sequences: &[u64]) {
+        for &sequence in sequences {
+            let entry = OutboxEntry {
+                sequence,
+                id: Uuid::new_v4(),
+                timestamp: at(sequence as i64),
+                event: if sequence % 2 == 1 { Open } else { Closed },
+                reconciliation: false,
+                previous: None,
+            };
+            let inputs = BTreeMap::from([("door".to_string(), entry.event)]);
+            history.record_event(&entry, "door", &inputs).unwrap();
+        }
+    }
+
+    fn sequences(events: &[HistoryEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.sequence).collect()
+    }
+
+    fn event(sequence: u64, source: &str, inputs: &[(&str, StatusEvent)], deliveries: &[(&str, DeliveryOutcome)]) -> HistoryEvent {
+        HistoryEvent {
+            sequence,
+            id: Uuid::nil(),
+            timestamp: at(sequence as i64),
+            status: Open,
+            source: source.to_string(),
+            reconciliation: false,
+            inputs: inputs.iter().map(|(name, status)| (name.to_string(), *status)).collect(),
+            deliveries: deliveries
+                .iter()
+                .map(|(endpoint, outcome)| {
+                    let delivery = Delivery {
+                        outcome: *outcome,
+                        timestamp: at(sequence as i64),
+                        error: None,
+                    };
+                    (endpoint.to_string(), delivery)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn queries_half_open_range() {
+        let dir = TempDir::new().unwrap();
+        let history = open(&dir);
+        record(&history, &[1, 2, 3, 4]);
+
+        assert_eq!(sequences(&history.query(None, None).unwrap()), [1, 2, 3, 4]);
+        assert_eq!(sequences(&history.query(Some(at(2)), Some(at(4))).unwrap()), [2, 3]);
+        assert_eq!(sequences(&history.query(Some(at(3)), None).unwrap()), [3, 4]);
+        assert_eq!(sequences(&history.query(None, Some(at(2))).unwrap()), [1]);
+        assert!(history.query(Some(at(5)), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn joins_deliveries_by_sequence() {
+        let dir = TempDir::new().unwrap();
+        let history = open(&dir);
+        record(&history, &[1, 2]);
+        history.record_delivery(2, "a", DeliveryOutcome::Rejected, Some("HTTP 400".to_string())).unwrap();
+        history.record_delivery(1, "a", DeliveryOutcome::Superseded, None).unwrap();
+        history.record_delivery(2, "b", DeliveryOutcome::Delivered, None).unwrap();
+
+        let events = history.query(None, None).unwrap();
+        assert_eq!(events[0].deliveries.len(), 1);
+        assert_eq!(events[0].deliveries["a"].outcome, DeliveryOutcome::Superseded);
+        assert_eq!(events[1].deliveries["a"].outcome, DeliveryOutcome::Rejected);
+        assert_eq!(events[1].deliveries["a"].error.as_deref(), Some("HTTP 400"));
+        assert_eq!(events[1].deliveries["b"].outcome, DeliveryOutcome::Delivered);
+    }
+
+    #[test]
+    fn drops_deliveries_of_events_outside_range() {
+        let dir = TempDir::new().unwrap();
+        let history = open(&dir);
+        record(&history, &[1, 2]);
+        history.record_delivery(1, "a", DeliveryOutcome::Delivered, None).unwrap();
+        history.record_delivery(3, "a", DeliveryOutcome::Delivered, None).unwrap();
+
+        let events = history.query(Some(at(2)), None).unwrap();
+        assert_eq!(sequences(&events), [2]);
+        assert!(events[0].deliveries.is_empty());
+    }
+
+    #[test]
+    fn skips_corrupt_lines() {
+        let dir = TempDir::new().unwrap();
+        let history = open(&dir);
+        record(&history, &[1]);
+        let mut file = OpenOptions::new().append(true).open(dir.path().join("history.jsonl")).unwrap();
+        writeln!(file, "{{\"kind\":\"event\",\"sequence\":").unwrap();
+        writeln!(file, "not json at all").unwrap();
+        record(&history, &[2]);
+        history.record_delivery(2, "a", DeliveryOutcome::Delivered, None).unwrap();
+
+        let events = history.query(None, None).unwrap();
+        assert_eq!(sequences(&events), [1, 2]);
+        assert_eq!(events[1].deliveries["a"].outcome, DeliveryOutcome::Delivered);
+    }
+
+    #[test]
+    fn csv_has_column_per_input_and_endpoint() {
+        let events = [
+            event(1, "door", &[("door", Open), ("window", Closed)], &[("a", DeliveryOutcome::Delivered)]),
+            event(2, "window", &[("window", Open)], &[("b", DeliveryOutcome::Rejected), ("a", DeliveryOutcome::Superseded)]),
+            event(3, "door", &[("door", Open)], &[]),
+        ];
+
+        let csv = to_csv(&events);
+        let lines: Vec<&str> = csv.split_terminator("\r\n").collect();
+        assert_eq!(lines, [
+            "sequence,id,timestamp,status,source,reconciliation,input_door,input_window,delivery_a,delivery_b",
+            "1,00000000-0000-0000-0000-000000000000,2025-06-02T01:00:00+00:00,open,door,false,open,closed,delivered,",
+            "2,00000000-0000-0000-0000-000000000000,2025-06-02T02:00:00+00:00,open,window,false,,open,superseded,rejected",
+            "3,00000000-0000-0000-0000-000000000000,2025-06-02T03:00:00+00:00,open,door,false,open,,,",
+        ]);
+    }
+
+    #[test]
+    fn csv_quotes_special_characters() {
+        let events = [event(1, "front, \"main\"\ndoor", &[], &[])];
+
+        let csv = to_csv(&events);
+        let row = csv.split_once("\r\n").unwrap().1;
+        assert_eq!(
+            row,
+            "1,00000000-0000-0000-0000-000000000000,2025-06-02T01:00:00+00:00,open,\"front, \"\"main\"\"\ndoor\",false\r\n"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{
+    config::{HomeAssistantConfig, MqttConfig},
+    delivery::Delivery,
+    outbox::Outbox,
+    status::StatusTracker,
+};
+
+/// Values behind the diagnostic entities, published as one JSON message.
+#[derive(Debug, Serialize)]
+struct Diagnostics {
+    last_notification_success: Option<bool>,
+    uptime_secs: i64,
+    outbox_length: usize,
+}
+
+/// Builds the Home Assistant MQTT discovery messages and diagnostics for this device.
+///
+/// The switch becomes a `binary_sensor` reading the regular state topic, and the diagnostic
+/// entities read their values from the diagnostics topic.
+pub struct HomeAssistant {
+    config: HomeAssistantConfig,
+    /// Identifies the device and its entities, so several noisebells can live in one Home
+    /// Assistant.
+    device_id: String,
+    state_topic: String,
+    availability_topic: String,
+    diagnostics_topic: String,
+    delivery: Arc<Delivery>,
+    outbox: Arc<Outbox>,
+    tracker: Arc<StatusTracker>,
+}
+
+impl HomeAssistant {
+    pub fn new(
+        config: HomeAssistantConfig,
+        mqtt: &MqttConfig,
+        device_id: &str,
+        delivery: Arc<Delivery>,
+        outbox: Arc<Outbox>,
+        tracker: Arc<StatusTracker>,
+    ) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            state_topic: mqtt.topic(device_id),
+            availability_topic: mqtt.availability_topic(device_id),
+            diagnostics_topic: config.diagnostics_topic(device_id),
+            config,
+            delivery,
+            outbox,
+            tracker,
+        }
+    }
+
+    pub fn diagnostics_topic(&self) -> &str {
+        &self.diagnostics_topic
+    }
+
+    /// Retained `(topic, payload)` config messages that make Home Assistant create the entities.
+    pub fn discovery_messages(&self) -> Vec<(String, String)> {
+        let device_id = &self.device_id;
+        let device = json!({
+            "identifiers": [device_id],
+            "name": self.config.device_name,
+            "manufacturer": "Noisebell",
+            "model": "noisebell-pi",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+
+        let entities = [
+            (
+                "binary_sensor",
+                "door",
+                json!({
+                    "name": null,
+                    "state_topic": self.state_topic,
+                    "payload_on": "open",
+                    "payload_off": "closed",
+                    "device_class": self.config.device_class,
+                }),
+            ),
+            (
+                "binary_sensor",
+                "last_notification",
+                // Shown as OK or Problem, and OK until the first notification
+                json!({
+                    "name": "Last notification",
+                    "state_topic": self.diagnostics_topic,
+                    "value_template": "{{ 'ON' if value_json.last_notification_success == false else 'OFF' }}",
+                    "device_class": "problem",
+                    "entity_category": "diagnostic",
+                }),
+            ),
+            (
+                "sensor",
+                "uptime",
+                json!({
+                    "name": "Uptime",
+                    "state_topic": self.diagnostics_topic,
+                    "value_template": "{{ value_json.uptime_secs }}",
+                    "device_class": "duration",
+                    "unit_of_measurement": "s",
+                    "entity_category": "diagnostic",
+                }),
+            ),
+            (
+                "sensor",
+                "outbox_length",
+                json!({
+                    "name": "Outbox length",
+                    "state_topic": self.diagnostics_topic,
+                    "value_template": "{{ value_json.outbox_length }}",
+                    "state_class": "measurement",
+                    "unit_of_measurement": "events",
+                    "entity_category": "diagnostic",
+                }),
+            ),
+        ];
+
+        entities
+            .into_iter()
+            .map(|(component, object_id, mut payload)| {
+                let unique_id = format!("{}_{}", device_id, object_id);
+                if let Value::Object(fields) = &mut payload {
+                    fields.insert("unique_id".to_string(), json!(unique_id));
+                    fields.insert("object_id".to_string(), json!(unique_id));
+                    fields.insert("availability_topic".to_string(), json!(self.availability_topic));
+                    fields.insert("device".to_string(), device.clone());
+                }
+
+                let topic = format!("{}/{}/{}/{}/config", self.config.discovery_prefix, component, device_id, object_id);
+                (topic, payload.to_string())
+            })
+            .collect()
+    }
+
+    pub fn diagnostics(&self) -> String {
+        let diagnostics = Diagnostics {
+            last_notification_success: self.delivery.last_result().map(|result| result.success),
+            uptime_secs: self.tracker.snapshot().uptime_secs,
+            outbox_length: self.outbox.pending_count(),
+        };
+
+        serde_json::to_string(&diagnostics).unwrap_or_default()
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Serialize;
+
+use crate::{StatusEvent, config::InputConfig, debounce, monitor::Monitor};
+
+/// How the states of several inputs are turned into the one published state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombineRule {
+    /// Open while any input is open.
+    Any,
+    /// Open only while every input is open.
+    All,
+    /// Open while more than half of the inputs are open.
+    Majority,
+    /// Follows the input with the highest priority, falling back to the next one when it stops.
+    Priority,
+}
+
+impl FromStr for CombineRule {
+    type Err = anyhow::Error;
+
+    fn from_str(rule: &str) -> Result<Self> {
+        match rule {
+            "any" => Ok(Self::Any),
+            "all" => Ok(Self::All),
+            "majority" => Ok(Self::Majority),
+            "priority" => Ok(Self::Priority),
+            rule => Err(anyhow::anyhow!("Unknown combine rule {}, expected any, all, majority or priority", rule)),
+        }
+    }
+}
+
+/// The debounced state of one input, as shown in the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct InputState {
+    pub name: String,
+    pub status: StatusEvent,
+    pub last_changed: DateTime<Utc>,
+    /// False once the input has stopped reporting, after which it no longer counts.
+    pub running: bool,
+    #[serde(skip)]
+    priority: u32,
+}
+
+/// The state of every input, and the published state derived from them.
+pub struct InputStates {
+    rule: CombineRule,
+    states: RwLock<Vec<InputState>>,
+}
+
+impl InputStates {
+    /// `inputs` are the name, priority and current state of each input, in configuration order.
+    pub fn new(rule: CombineRule, inputs: Vec<(String, u32, StatusEvent)>) -> Self {
+        let now = Utc::now();
+        let states = inputs
+            .into_iter()
+            .map(|(name, priority, status)| InputState {
+                name,
+                status,
+                last_changed: now,
+                running: true,
+                priority,
+            })
+            .collect();
+
+        Self {
+            rule,
+            states: RwLock::new(states),
+        }
+    }
+
+    pub fn set(&self, index: usize, status: StatusEvent) {
+        let mut states = self.states.write().unwrap();
+        let state = &mut states[index];
+        if state.status != status {
+            state.status = status;
+            state.last_changed = Utc::now();
+        }
+    }
+
+    pub fn stop(&self, index: usize) {
+        self.states.write().unwrap()[index].running = false;
+    }
+
+    /// The state to publish according to the combine rule, or `None` once every input has
+    /// stopped.
+    pub fn combined(&self) -> Option<StatusEvent> {
+        let states = self.states.read().unwrap();
+        let running: Vec<&InputState> = states.iter().filter(|state| state.running).collect();
+        if running.is_empty() {
+            return None;
+        }
+
+        let open = running.iter().filter(|state| state.status == StatusEvent::Open).count();
+        let is_open = match self.rule {
+            CombineRule::Any => open > 0,
+            CombineRule::All => open == running.len(),
+            CombineRule::Majority => open * 2 > running.len(),
+            CombineRule::Priority => {
+                // The first listed input wins a tie
+                let leader = running.iter().rev().max_by_key(|state| state.priority)?;
+                return Some(leader.status);
+            }
+        };
+
+        Some(if is_open { StatusEvent::Open } else { StatusEvent::Closed })
+    }
+
+    pub fn snapshot(&self) -> Vec<InputState> {
+        self.states.read().unwrap().clone()
+    }
+
+    /// The state of each input by name, as recorded in the history.
+    pub fn statuses(&self) -> BTreeMap<String, StatusEvent> {
+        self.states
+            .read()
+            .unwrap()
+            .iter()
+            .map(|state| (state.name.clone(), state.status))
+            .collect()
+    }
+}
+
+/// A monitor with the polarity and debounce settings of its input.
+pub struct Input {
+    pub name: String,
+    pub priority: u32,
+    monitor: Box<dyn Monitor>,
+    inverted: bool,
+    debounce: debounce::Settings,
+}
+
+impl Input {
+    pub fn new(config: &InputConfig, monitor: Box<dyn Monitor>, debounce: debounce::Settings) -> Self {
+        Self {
+            name: config.name.clone(),
+            priority: config.priority,
+            monitor,
+            inverted: config.polarity == "inverted",
+            debounce,
+        }
+    }
+
+    pub async fn current_state(&self) -> StatusEvent {
+        apply_polarity(self.inverted, self.monitor.current_state().await)
+    }
+
+    /// Starts the monitor and returns its debounced changes, starting from `initial`. The stream
+    /// ends with `None` when the monitor stops.
+    ///
+    /// The monitor must be kept alive for as long as the stream is used.
+    pub fn events(&mut self, initial: StatusEvent) -> Result<BoxStream<'static, Option<StatusEvent>>> {
+        let inverted = self.inverted;
+        let events = self.monitor.events()?.map(move |status| apply_polarity(inverted, status));
+
+        Ok(debounce::debounce(events.boxed(), self.debounce, initial)
+            .map(Some)
+            .chain(stream::once(async { None }))
+            .boxed())
+    }
+}
+
+// An inverted input is wired the other way round, so it reads open when the space is closed
+fn apply_polarity(inverted: bool, status: StatusEvent) -> StatusEvent {
+    match (inverted, status) {
+        (false, status) => status,
+        (true, StatusEvent::Open) => StatusEvent::Closed,
+        (true, StatusEvent::Closed) => StatusEvent::Open,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use StatusEvent::{Closed, Open};
+
+    fn states(rule: CombineRule, inputs: &[(u32, StatusEvent)]) -> InputStates {
+        let inputs = inputs
+            .iter()
+            .enumerate()
+            .map(|(index, (priority, status))| (format!("input{}", index), *priority, *status))
+            .collect();
+        InputStates::new(rule, inputs)
+    }
+
+    #[test]
+    fn any_is_open_when_one_input_is() {
+        let states = states(CombineRule::Any, &[(0, Closed), (0, Open), (0, Closed)]);
+        assert_eq!(states.combined(), Some(Open));
+
+        states.set(1, Closed);
+        assert_eq!(states.combined(), Some(Closed));
+    }
+
+    #[test]
+    fn all_needs_every_input_open() {
+        let states = states(CombineRule::All, &[(0, Open), (0, Open), (0, Closed)]);
+        assert_eq!(states.combined(), Some(Closed));
+
+        states.set(2, Open);
+        assert_eq!(states.combined(), Some(Open));
+    }
+
+    #[test]
+    fn majority_needs_more_than_half() {
+        let states = states(CombineRule::Majority, &[(0, Open), (0, Closed), (0, Closed), (0, Open)]);
+        assert_eq!(states.combined(), Some(Closed));
+
+        states.set(1, Open);
+        assert_eq!(states.combined(), Some(Open));
+    }
+
+    #[test]
+    fn priority_follows_highest_and_fails_over() {
+        let states = states(CombineRule::Priority, &[(1, Closed), (10, Open), (10, Closed)]);
+        assert_eq!(states.combined(), Some(Open));
+
+        states.stop(1);
+        assert_eq!(states.combined(), Some(Closed));
+
+        states.set(0, Open);
+        states.stop(2);
+        assert_eq!(states.combined(), Some(Open));
+    }
+
+    #[test]
+    fn stopped_inputs_do_not_count() {
+        let states = states(CombineRule::All, &[(0, Open), (0, Closed)]);
+        states.stop(1);
+        assert_eq!(states.combined(), Some(Open));
+
+        states.stop(0);
+        assert_eq!(states.combined(), None);
+    }
+}
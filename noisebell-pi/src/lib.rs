@@ -0,0 +1,3 @@
+//! Parts of noisebell that are useful to the services receiving its notifications.
+
+pub mod signing;
@@ -1,11 +1,15 @@
 use std::fs;
 use anyhow::Result;
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_appender::rolling::RollingFileAppender;
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 use crate::config::LoggingConfig;
 
-pub fn init(config: &LoggingConfig) -> Result<()> {
+/// Sets up logging to stdout and the log file.
+///
+/// The returned guard flushes the log file when dropped, so keep it alive until exit.
+pub fn init(config: &LoggingConfig) -> Result<WorkerGuard> {
     tracing::info!("creating logs directory");
     let log_dir = std::path::Path::new(&config.file_path).parent().unwrap_or_else(|| std::path::Path::new("logs"));
     fs::create_dir_all(log_dir)?;
@@ -17,7 +21,7 @@ pub fn init(config: &LoggingConfig) -> Result<()> {
         .filename_suffix("log")
         .build(log_dir)?;
 
-    let (non_blocking, _guard) = tracing_appender::non_blocking::NonBlockingBuilder::default()
+    let (non_blocking, guard) = tracing_appender::non_blocking::NonBlockingBuilder::default()
         .buffered_lines_limit(config.max_buffered_lines)
         .finish(file_appender);
 
@@ -43,5 +47,5 @@ pub fn init(config: &LoggingConfig) -> Result<()> {
         .with(fmt::Layer::default().with_writer(non_blocking))
         .init();
 
-    Ok(())
+    Ok(guard)
 } 
\ No newline at end of file
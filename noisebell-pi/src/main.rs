@@ -1,21 +1,43 @@
 mod logging;
 mod monitor;
+mod pin;
+#[cfg(test)]
+mod mock_pin;
 mod gpio_monitor;
+mod gpiod_monitor;
 mod web_monitor;
+mod web_server;
+mod notifier;
+mod delivery;
 mod endpoint_notifier;
+mod slack_notifier;
+mod matrix_notifier;
+mod zulip_notifier;
+mod debounce;
+mod inputs;
+mod outbox;
+mod dispatcher;
+mod state_store;
+mod status;
+mod history;
+mod stats;
+mod spaceapi;
+mod mqtt;
+mod home_assistant;
+mod template;
+mod discord;
 mod config;
 
-use std::{fmt, sync::Arc};
-use tokio::sync::RwLock;
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, watch};
 
 use anyhow::Result;
+use futures::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
-
-// Shared state types
-pub type SharedMonitor = Arc<RwLock<Box<dyn monitor::Monitor>>>;
+use tracing::{error, info, warn};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum StatusEvent {
     Open,
     Closed,
@@ -33,66 +55,281 @@ impl fmt::Display for StatusEvent {
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load and validate configuration
-    let config = config::Config::from_env()?;
+    let config_path = config::Config::path_from_args(std::env::args())?;
+    let config = config::Config::load(config_path.as_deref())?;
     config.validate()?;
     
     info!("Configuration loaded successfully");
-    info!("Monitor type: {}", config.monitor.monitor_type);
+    let input_configs = config.inputs();
+    for input in &input_configs {
+        info!("Input '{}': {}", input.name, input.monitor_type);
+    }
     if config.web_monitor.enabled {
-        info!("Web monitor: port {}", config.web_monitor.port);
+        info!("Web server: port {}", config.web_monitor.port);
     }
 
-    // Initialize logging with config
-    logging::init(&config.logging)?;
+    // Initialize logging with config. The guard flushes the log file when main returns.
+    let _log_guard = logging::init(&config.logging)?;
 
     // Load endpoint configuration
-    info!("Using endpoint URL: {}", config.endpoint.url);
-    let endpoint_config = endpoint_notifier::EndpointConfig {
-        url: config.endpoint.url.clone(),
-        api_key: config.endpoint.api_key.clone(),
-        timeout_secs: config.endpoint.timeout_secs,
-        retry_attempts: config.endpoint.retry_attempts,
+    for key in config.ignored_legacy_env() {
+        warn!("{} is set but ignored, it only applies to an endpoint named 'default'", key);
+    }
+    for endpoint in &config.endpoints {
+        // Slack and Discord webhook URLs are secrets of their own
+        let url = if ["slack", "discord"].contains(&endpoint.kind.as_str()) { "(webhook)" } else { endpoint.url.as_str() };
+        info!("Using {} endpoint '{}': {}", endpoint.kind, endpoint.name, url);
+    }
+    let delivery = Arc::new(delivery::Delivery::new(config.endpoints.clone(), &config.monitor.device_id)?);
+
+    // Permanent record of every state change and where it was delivered
+    let history = Arc::new(history::History::open(&config.history.path)?);
+
+    // Every event goes through the on-disk outbox so nothing is lost during outages or restarts
+    let outbox = Arc::new(outbox::Outbox::open(
+        &config.outbox.path,
+        delivery.enabled_endpoints(),
+        config.outbox.max_entries,
+        history.clone(),
+    )?);
+
+    // Set once a shutdown signal arrives, telling the workers and the web server to stop
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let mut tasks = Vec::new();
+    for endpoint in config.endpoints.iter().filter(|endpoint| endpoint.enabled) {
+        tasks.push(tokio::spawn(outbox.clone().run_worker(
+            delivery.clone(),
+            endpoint.name.clone(),
+            endpoint.coalesce,
+            Duration::from_secs(config.outbox.retry_interval_secs),
+            shutdown_rx.clone(),
+        )));
+    }
+
+    // Tracks the published state for the web server and its API
+    let tracker = Arc::new(status::StatusTracker::new());
+
+    // Last published state from the previous run, to catch changes made while we were down
+    let state_store = Arc::new(state_store::StateStore::open(&config.state.path)?);
+    let last_state = state_store.load().unwrap_or_else(|e| {
+        warn!("Ignoring last known state: {}", e);
+        None
+    });
+
+    // Every state change goes through this one queue, so they are recorded and delivered in order
+    let (publisher, dispatcher) = dispatcher::channel(
+        outbox.clone(),
+        history.clone(),
+        state_store.clone(),
+        tracker.clone(),
+        last_state,
+    );
+    tasks.push(tokio::spawn(dispatcher.run(shutdown_rx.clone())));
+
+    // Switch changes made on the web page. They drive the web input, or override the real
+    // inputs when that is allowed.
+    let (manual_tx, manual_rx) = mpsc::unbounded_channel();
+    let web_input = input_configs.iter().any(|input| input.monitor_type == "web");
+    let manual_control = web_input || config.web_monitor.allow_override;
+    let (mut monitor_input, override_input) = if web_input {
+        (Some(manual_rx), None)
+    } else {
+        (None, Some(manual_rx))
+    };
+
+    let mut inputs = Vec::new();
+    for input in &input_configs {
+        info!("initializing {} monitor for input '{}'", input.monitor_type, input.name);
+        let web_input = if input.monitor_type == "web" { monitor_input.take() } else { None };
+        let monitor = monitor::create_monitor(input, web_input, last_state.map(|last| last.status))?;
+        let settings = input.debounce.settings(&input.monitor_type, config.gpio.debounce_delay_secs);
+        inputs.push(inputs::Input::new(input, monitor, settings));
+    }
+
+    let mut initial_states = Vec::new();
+    for input in &inputs {
+        initial_states.push((input.name.clone(), input.priority, input.current_state().await));
+    }
+    let input_states = Arc::new(inputs::InputStates::new(config.monitor.combine.parse()?, initial_states.clone()));
+    let current_state = input_states.combined().unwrap_or(StatusEvent::Closed);
+    // Reconciliation is not caused by any one input when there are several
+    let source = match inputs.as_slice() {
+        [input] => input.name.clone(),
+        _ => "combined".to_string(),
     };
-    let notifier = Arc::new(endpoint_notifier::EndpointNotifier::new(endpoint_config));
 
-    info!("initializing {} monitor", config.monitor.monitor_type);
-    let monitor = monitor::create_monitor(
-        &config.monitor.monitor_type,
-        config.gpio.pin,
-        config.get_debounce_delay(),
-        if config.web_monitor.enabled { Some(config.web_monitor.port) } else { None },
-    )?;
+    match last_state {
+        Some(last) if last.status == current_state => {
+            info!("Input is {}, matching the last reported state", current_state);
+            tracker.set_initial(current_state, Some(last.timestamp));
+        }
+        Some(last) => {
+            warn!(
+                "Input changed from {} to {} while noisebell was not running, sending reconciliation event",
+                last.status, current_state
+            );
+            publisher.publish(current_state, &source, true, input_states.statuses()).await;
+        }
+        None => {
+            info!("No state reported before, sending reconciliation event for {} state", current_state);
+            publisher.publish(current_state, &source, true, input_states.statuses()).await;
+        }
+    }
 
-    let shared_monitor: SharedMonitor = Arc::new(RwLock::new(monitor));
+    if config.spaceapi.enabled && config.spaceapi.push_url.is_some() {
+        tasks.push(tokio::spawn(spaceapi::run_pusher(
+            config.spaceapi.clone(),
+            tracker.clone(),
+            shutdown_rx.clone(),
+        )));
+    }
 
-    let monitor_for_task = shared_monitor.clone();
-    
-    let callback = {
-        let notifier = notifier.clone();
-        Box::new(move |event: StatusEvent| {            
-            let notifier = notifier.clone();
-            tokio::spawn(async move {
-                if let Err(e) = notifier.notify_endpoint(event).await {
-                    error!("Failed to notify endpoint: {}", e);
+    if config.mqtt.enabled {
+        let mut publisher = mqtt::MqttPublisher::new(config.mqtt.clone(), &config.monitor.device_id, tracker.clone());
+        if config.home_assistant.enabled {
+            let home_assistant = home_assistant::HomeAssistant::new(
+                config.home_assistant.clone(),
+                &config.mqtt,
+                &config.monitor.device_id,
+                delivery.clone(),
+                outbox.clone(),
+                tracker.clone(),
+            );
+            let interval = Duration::from_secs(config.home_assistant.diagnostics_interval_secs);
+            publisher = publisher.with_home_assistant(home_assistant, interval);
+        }
+        tasks.push(tokio::spawn(publisher.run(shutdown_rx.clone())));
+    }
+
+    if let Some(mut overrides) = override_input.filter(|_| config.web_monitor.allow_override) {
+        let publisher = publisher.clone();
+        let input_states = input_states.clone();
+        let mut shutdown = shutdown_rx.clone();
+        tasks.push(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    event = overrides.recv() => match event {
+                        Some(event) => {
+                            warn!("Manual override from web page, publishing {} state", event);
+                            publisher.publish(event, "override", false, input_states.statuses()).await;
+                        }
+                        None => return,
+                    },
+                    _ = shutdown.changed() => return,
                 }
-            });
-        })
-    };
+            }
+        }));
+    }
 
-    let monitor_handle = tokio::spawn(async move {
-        if let Err(e) = monitor_for_task.write().await.monitor(callback) {
-            error!("Monitor error: {}", e);
+    if config.web_monitor.enabled {
+        let server = web_server::WebServer::new(
+            config.web_monitor.port,
+            delivery.clone(),
+            tracker.clone(),
+            history.clone(),
+            input_states.clone(),
+            config.spaceapi.enabled.then(|| Arc::new(config.spaceapi.clone())),
+            manual_control.then_some(manual_tx),
+        );
+        let shutdown = shutdown_rx.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = server.run(shutdown).await {
+                error!("Web server error: {}", e);
+            }
+        }));
+    }
+
+    // Each input starts from the state read above, so only later changes are announced
+    let mut streams = Vec::new();
+    for (index, (input, (_, _, initial))) in inputs.iter_mut().zip(initial_states).enumerate() {
+        streams.push(input.events(initial)?.map(move |status| (index, status)).boxed());
+    }
+    let mut events = stream::select_all(streams);
+
+    let names: Vec<String> = inputs.iter().map(|input| input.name.clone()).collect();
+    let mut monitor_handle = tokio::spawn(async move {
+        while let Some((index, status)) = events.next().await {
+            let previous = input_states.combined();
+            match status {
+                Some(status) => {
+                    info!("Input '{}' is {}", names[index], status);
+                    input_states.set(index, status);
+                }
+                None => {
+                    warn!("Input '{}' stopped, it no longer counts towards the state", names[index]);
+                    input_states.stop(index);
+                }
+            }
+
+            match input_states.combined() {
+                Some(combined) if Some(combined) != previous => {
+                    publisher.publish(combined, &names[index], false, input_states.statuses()).await;
+                }
+                _ => {}
+            }
         }
     });
 
     info!("Monitor started with endpoint notifications.");
 
     tokio::select! {
-        _ = monitor_handle => {
-            info!("Monitor task completed");
+        _ = &mut monitor_handle => {
+            warn!("Monitor task completed");
         }
+        _ = shutdown_signal() => {}
     }
-    
+
     info!("Shutting down noisebell...");
+
+    // Stop taking new input first so nothing is queued after this point
+    monitor_handle.abort();
+    drop(inputs);
+    let _ = shutdown_tx.send(true);
+
+    // Give in-flight notifications a chance to finish. Whatever is left stays in the outbox.
+    let deadline = Duration::from_secs(config.outbox.shutdown_timeout_secs);
+    if tokio::time::timeout(deadline, futures::future::join_all(tasks)).await.is_err() {
+        warn!("Notifications still in flight after {:?}, stopping anyway", deadline);
+    }
+
+    let pending = outbox.pending_count();
+    if pending > 0 {
+        info!("{} event(s) will be delivered after restart", pending);
+    }
+
+    info!("Shutdown complete");
     Ok(())
 }
+
+/// Waits for SIGTERM (sent by systemd on stop) or Ctrl+C.
+async fn shutdown_signal() {
+    let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            error!("Failed to listen for SIGTERM: {}", e);
+            None
+        }
+    };
+
+    let terminate = async {
+        match terminate.as_mut() {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate => info!("Received SIGTERM"),
+        _ = interrupt => info!("Received SIGINT"),
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder, Url};
+use serde_json::json;
+
+use crate::{
+    config::EndpointConfig,
+    notifier::{self, AttemptError, Notifier, check_response},
+    outbox::OutboxEntry,
+};
+
+/// Sends a text message to a Matrix room through the client-server API.
+///
+/// The account behind the access token must already have joined the room.
+pub struct MatrixNotifier {
+    client: Client,
+    homeserver: Url,
+    access_token: String,
+    room_id: String,
+    message_template: String,
+    device_id: String,
+    timeout: Duration,
+}
+
+impl MatrixNotifier {
+    pub fn new(config: &EndpointConfig, client: Client, device_id: &str) -> Result<Self> {
+        let homeserver = Url::parse(&config.url)
+            .map_err(|e| anyhow::anyhow!("Invalid Matrix homeserver URL for endpoint '{}': {}", config.name, e))?;
+        if homeserver.cannot_be_a_base() {
+            return Err(anyhow::anyhow!("Invalid Matrix homeserver URL for endpoint '{}'", config.name));
+        }
+
+        Ok(Self {
+            client,
+            homeserver,
+            access_token: config.api_key.clone().unwrap_or_default(),
+            room_id: config.room_id.clone().unwrap_or_default(),
+            message_template: config.message_template.clone(),
+            device_id: device_id.to_string(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    /// The transaction ID stays the same across retries of an event, so the homeserver drops
+    /// duplicates when a response was lost.
+    fn send_url(&self, entry: &OutboxEntry) -> Url {
+        let transaction_id = format!("noisebell-{}", entry.id);
+
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("checked in new")
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3", "rooms", &self.room_id, "send", "m.room.message", &transaction_id]);
+        url
+    }
+
+    fn request(&self, entry: &OutboxEntry) -> RequestBuilder {
+        let payload = json!({
+            "msgtype": "m.text",
+            "body": notifier::render_message(&self.message_template, entry, &self.device_id),
+        });
+
+        self.client
+            .put(self.send_url(entry))
+            .bearer_auth(&self.access_token)
+            .json(&payload)
+            .timeout(self.timeout)
+    }
+}
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    async fn send(&self, entry: &OutboxEntry) -> Result<(), AttemptError> {
+        let response = self.request(entry)
+            .send()
+            .await?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use crate::StatusEvent;
+
+    fn entry() -> OutboxEntry {
+        OutboxEntry {
+            sequence: 7,
+            id: Uuid::nil(),
+            timestamp: Utc.with_ymd_and_hms(2025, 6, 2, 18, 0, 0).unwrap(),
+            event: StatusEvent::Open,
+            reconciliation: false,
+            previous: None,
+        }
+    }
+
+    fn notifier(homeserver: &str) -> MatrixNotifier {
+        let config = EndpointConfig {
+            kind: "matrix".to_string(),
+            url: homeserver.to_string(),
+            api_key: Some("token".to_string()),
+            room_id: Some("!room:example.org".to_string()),
+            message_template: "The space is {{status}}".to_string(),
+            ..EndpointConfig::default()
+        };
+        MatrixNotifier::new(&config, Client::new(), "pi").unwrap()
+    }
+
+    #[test]
+    fn puts_message_with_transaction_id() {
+        let request = notifier("https://matrix.example.org").request(&entry()).build().unwrap();
+
+        assert_eq!(request.method(), "PUT");
+        assert_eq!(
+            request.url().as_str(),
+            "https://matrix.example.org/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/noisebell-00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(request.headers()["authorization"], "Bearer token");
+        let body: serde_json::Value = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body, json!({ "msgtype": "m.text", "body": "The space is open" }));
+    }
+
+    #[test]
+    fn keeps_homeserver_path() {
+        let url = notifier("https://example.org/matrix/").send_url(&entry());
+        assert!(url.as_str().starts_with("https://example.org/matrix/_matrix/client/v3/rooms/"), "{}", url);
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::pin::{Edge, EdgeCallback, InputPin, Level};
+
+/// In-memory [`InputPin`] that plays back a script of level changes, for tests.
+///
+/// The script starts when edges are requested and runs on tokio's clock, so tests with a
+/// paused clock get exact timings without waiting. The callback is dropped once the script
+/// ends, which ends the monitor's event stream.
+pub struct MockPin {
+    level: Arc<Mutex<Level>>,
+    /// Each step is a delay after the previous step and the level to change to.
+    script: Vec<(Duration, Level)>,
+}
+
+impl MockPin {
+    pub fn new(initial: Level) -> Self {
+        Self {
+            level: Arc::new(Mutex::new(initial)),
+            script: Vec::new(),
+        }
+    }
+
+    /// Changes to `level` once `after` has passed since the previous step.
+    pub fn then(mut self, after: Duration, level: Level) -> Self {
+        self.script.push((after, level));
+        self
+    }
+
+    /// Like [`then`](Self::then), but bounces back and forth `bounces` times, `interval` apart,
+    /// before settling on `level`.
+    pub fn bounce(mut self, after: Duration, level: Level, bounces: u32, interval: Duration) -> Self {
+        let mut delay = after;
+        for _ in 0..bounces {
+            self = self.then(delay, level).then(interval, opposite(level));
+            delay = interval;
+        }
+        self.then(delay, level)
+    }
+}
+
+fn opposite(level: Level) -> Level {
+    match level {
+        Level::Low => Level::High,
+        Level::High => Level::Low,
+    }
+}
+
+impl InputPin for MockPin {
+    fn read(&self) -> Level {
+        *self.level.lock().unwrap()
+    }
+
+    fn on_edge(&mut self, mut callback: EdgeCallback) -> Result<()> {
+        let script = std::mem::take(&mut self.script);
+        let level = self.level.clone();
+
+        tokio::spawn(async move {
+            for (after, next) in script {
+                tokio::time::sleep(after).await;
+
+                let edge = {
+                    let mut level = level.lock().unwrap();
+                    if *level == next {
+                        continue;
+                    }
+                    *level = next;
+                    match next {
+                        Level::High => Edge::Rising,
+                        Level::Low => Edge::Falling,
+                    }
+                };
+                callback(edge);
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -1,19 +1,28 @@
-use std::time::Duration;
 use anyhow::Result;
-use crate::StatusEvent;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use tokio::sync::mpsc;
+use crate::{StatusEvent, config::InputConfig};
 
+#[async_trait]
 pub trait Monitor: Send + Sync {
-    fn monitor(&mut self, callback: Box<dyn FnMut(StatusEvent) + Send>) -> Result<()>;
-    fn get_current_state(&self) -> StatusEvent;
+    /// Starts watching the input and returns its state changes.
+    fn events(&mut self) -> Result<BoxStream<'static, StatusEvent>>;
+    async fn current_state(&self) -> StatusEvent;
 }
 
-pub fn create_monitor(monitor_type: &str, pin_number: u8, debounce_delay: Duration, web_port: Option<u16>) -> Result<Box<dyn Monitor>> {
-    match monitor_type {
-        "gpio" => Ok(Box::new(crate::gpio_monitor::GpioMonitor::new(pin_number, debounce_delay)?)),
+pub fn create_monitor(
+    input: &InputConfig,
+    web_input: Option<mpsc::UnboundedReceiver<StatusEvent>>,
+    last_state: Option<StatusEvent>,
+) -> Result<Box<dyn Monitor>> {
+    match input.monitor_type.as_str() {
+        "gpio" => Ok(Box::new(crate::gpio_monitor::GpioMonitor::new(input.pin)?)),
+        "gpiod" => Ok(Box::new(crate::gpiod_monitor::GpiodMonitor::new(&input.gpiod())?)),
         "web" => {
-            let port = web_port.ok_or_else(|| anyhow::anyhow!("Web monitor requires a port number"))?;
-            Ok(Box::new(crate::web_monitor::WebMonitor::new(port)?))
+            let web_input = web_input.ok_or_else(|| anyhow::anyhow!("Web monitor requires the web server"))?;
+            Ok(Box::new(crate::web_monitor::WebMonitor::new(web_input, last_state.unwrap_or(StatusEvent::Closed))?))
         },
-        _ => Err(anyhow::anyhow!("Unknown monitor type: {}", monitor_type)),
+        monitor_type => Err(anyhow::anyhow!("Unknown monitor type: {}", monitor_type)),
     }
-} 
\ No newline at end of file
+}
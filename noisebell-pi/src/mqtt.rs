@@ -0,0 +1,146 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::sync::{broadcast, watch};
+use tracing::{error, info, warn};
+
+use crate::{StatusEvent, config::MqttConfig, home_assistant::HomeAssistant, status::StatusTracker};
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Publishes the state to an MQTT broker as a retained QoS 1 message.
+///
+/// The broker is told to set the availability topic to `offline` if the connection drops, and
+/// after every (re)connect the availability and current state are published again, so the
+/// retained messages are always up to date.
+pub struct MqttPublisher {
+    config: MqttConfig,
+    client_id: String,
+    topic: String,
+    availability_topic: String,
+    tracker: Arc<StatusTracker>,
+    /// Discovery and diagnostics, sent over the same connection when enabled.
+    home_assistant: Option<(HomeAssistant, Duration)>,
+}
+
+impl MqttPublisher {
+    /// The client ID and topics not set in `config` are derived from `device_id`.
+    pub fn new(config: MqttConfig, device_id: &str, tracker: Arc<StatusTracker>) -> Self {
+        Self {
+            client_id: config.client_id(device_id),
+            topic: config.topic(device_id),
+            availability_topic: config.availability_topic(device_id),
+            config,
+            tracker,
+            home_assistant: None,
+        }
+    }
+
+    /// Also publishes Home Assistant discovery, and the diagnostics every `diagnostics_interval`.
+    pub fn with_home_assistant(mut self, home_assistant: HomeAssistant, diagnostics_interval: Duration) -> Self {
+        self.home_assistant = Some((home_assistant, diagnostics_interval));
+        self
+    }
+
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        let mut options = MqttOptions::new(&self.client_id, &self.config.host, self.config.port);
+        options.set_keep_alive(Duration::from_secs(self.config.keep_alive_secs));
+        options.set_last_will(LastWill::new(&self.availability_topic, OFFLINE, QoS::AtLeastOnce, true));
+        if let Some(username) = &self.config.username {
+            options.set_credentials(username, self.config.password.clone().unwrap_or_default());
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 16);
+        let mut updates = self.tracker.subscribe();
+        let reconnect_delay = Duration::from_secs(self.config.reconnect_delay_secs);
+
+        // The timer is only polled with Home Assistant enabled, so the fallback never fires
+        let diagnostics_interval = self.home_assistant.as_ref().map_or(Duration::from_secs(3600), |(_, interval)| *interval);
+        let mut diagnostics_timer = tokio::time::interval(diagnostics_interval);
+        diagnostics_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        info!("Connecting to MQTT broker at {}:{}", self.config.host, self.config.port);
+
+        loop {
+            tokio::select! {
+                event = eventloop.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker at {}:{}", self.config.host, self.config.port);
+                        if let Some((home_assistant, _)) = &self.home_assistant {
+                            for (topic, payload) in home_assistant.discovery_messages() {
+                                self.publish(&client, &topic, &payload);
+                            }
+                        }
+                        self.publish(&client, &self.availability_topic, ONLINE);
+                        if let Some(status) = self.tracker.current() {
+                            self.publish_state(&client, status);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // Polling again reconnects, so just wait a bit before that
+                        warn!("MQTT connection error: {}, reconnecting in {}s", e, reconnect_delay.as_secs());
+                        tokio::select! {
+                            _ = tokio::time::sleep(reconnect_delay) => {}
+                            _ = shutdown.changed() => return,
+                        }
+                    }
+                },
+                update = updates.recv() => match update {
+                    Ok(status) => self.publish_state(&client, status),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        if let Some(status) = self.tracker.current() {
+                            self.publish_state(&client, status);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                _ = diagnostics_timer.tick(), if self.home_assistant.is_some() => self.publish_diagnostics(&client),
+                _ = shutdown.changed() => {
+                    self.disconnect(&client, &mut eventloop).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn publish_state(&self, client: &AsyncClient, status: StatusEvent) {
+        self.publish(client, &self.topic, &status.to_string());
+        self.publish_diagnostics(client);
+    }
+
+    fn publish_diagnostics(&self, client: &AsyncClient) {
+        if let Some((home_assistant, _)) = &self.home_assistant {
+            self.publish(client, home_assistant.diagnostics_topic(), &home_assistant.diagnostics());
+        }
+    }
+
+    /// Queues a retained message. While disconnected it is sent once the connection is back.
+    fn publish(&self, client: &AsyncClient, topic: &str, payload: &str) {
+        if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+            error!("Failed to publish to MQTT topic {}: {}", topic, e);
+        }
+    }
+
+    /// Marks the device offline and disconnects cleanly, which does not trigger the Last Will.
+    async fn disconnect(&self, client: &AsyncClient, eventloop: &mut EventLoop) {
+        self.publish(client, &self.availability_topic, OFFLINE);
+        if client.try_disconnect().is_err() {
+            return;
+        }
+
+        let flush = async {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        };
+        if tokio::time::timeout(Duration::from_secs(2), flush).await.is_err() {
+            warn!("Timed out disconnecting from MQTT broker");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Response, StatusCode, header::RETRY_AFTER};
+use thiserror::Error;
+
+use crate::{config::EndpointConfig, outbox::OutboxEntry, template};
+
+/// Why a single request to an endpoint failed.
+#[derive(Debug, Error)]
+pub enum AttemptError {
+    #[error("HTTP request failed with status {status}: {body}")]
+    Status {
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    #[error(transparent)]
+    Transport(#[from] reqwest::Error),
+}
+
+impl AttemptError {
+    /// Client errors will never succeed on retry, except for timeouts and rate limiting.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            AttemptError::Status { status, .. } => {
+                status.is_client_error()
+                    && *status != StatusCode::REQUEST_TIMEOUT
+                    && *status != StatusCode::TOO_MANY_REQUESTS
+            }
+            AttemptError::Transport(_) => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AttemptError::Status { retry_after, .. } => *retry_after,
+            AttemptError::Transport(_) => None,
+        }
+    }
+}
+
+/// Sends state changes to one endpoint, such as an HTTP API or a chat room.
+///
+/// Each call makes a single attempt. Retries, backoff and the circuit breaker are handled by
+/// [`Delivery`](crate::delivery::Delivery), the same way for every kind of endpoint.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, entry: &OutboxEntry) -> Result<(), AttemptError>;
+}
+
+/// `device_id` identifies this noisebell in payload and message templates.
+pub fn create_notifier(config: &EndpointConfig, client: Client, device_id: &str) -> Result<Box<dyn Notifier>> {
+    match config.kind.as_str() {
+        "http" => Ok(Box::new(crate::endpoint_notifier::EndpointNotifier::new(config, client, device_id)?)),
+        "slack" => Ok(Box::new(crate::slack_notifier::SlackNotifier::new(config, client, device_id))),
+        "matrix" => Ok(Box::new(crate::matrix_notifier::MatrixNotifier::new(config, client, device_id)?)),
+        "zulip" => Ok(Box::new(crate::zulip_notifier::ZulipNotifier::new(config, client, device_id)?)),
+        "discord" => Ok(Box::new(crate::discord::DiscordNotifier::new(config, client, device_id)?)),
+        _ => Err(anyhow::anyhow!("Unknown endpoint kind: {}", config.kind)),
+    }
+}
+
+/// The variables available to payload and message templates.
+pub fn variables(entry: &OutboxEntry, device_id: &str) -> Vec<(&'static str, String)> {
+    let previous_duration = entry.previous.map(|previous| (entry.timestamp - previous.timestamp).num_seconds().max(0));
+
+    vec![
+        ("status", entry.event.to_string()),
+        ("previous_status", entry.previous.map_or_else(|| "unknown".to_string(), |previous| previous.status.to_string())),
+        ("timestamp", entry.timestamp.to_rfc3339()),
+        ("timestamp_unix", entry.timestamp.timestamp().to_string()),
+        ("duration", previous_duration.map_or_else(|| "unknown".to_string(), template::human_duration)),
+        ("duration_secs", previous_duration.unwrap_or(0).to_string()),
+        ("device_id", device_id.to_string()),
+        ("event_id", entry.id.to_string()),
+        ("sequence", entry.sequence.to_string()),
+        ("reconciliation", entry.reconciliation.to_string()),
+    ]
+}
+
+/// Renders a chat message template for an event.
+pub fn render_message(template: &str, entry: &OutboxEntry, device_id: &str) -> String {
+    template::render(template, &variables(entry, device_id))
+}
+
+/// Turns a non-success response into an [`AttemptError`] carrying its body and `Retry-After`.
+pub async fn check_response(response: Response) -> Result<Response, AttemptError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+
+    Err(AttemptError::Status {
+        status,
+        body: response.text().await.unwrap_or_else(|_| "Unknown error".to_string()),
+        retry_after,
+    })
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    StatusEvent,
+    delivery::{Delivery, NotifyError},
+    history::{DeliveryOutcome, History},
+    state_store::LastState,
+};
+
+/// A state change waiting to be delivered, as stored on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Increases with every event and survives restarts, so receivers can drop stale events.
+    pub sequence: u64,
+    /// Unique ID, sent as the idempotency key so receivers can drop retried duplicates.
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub event: StatusEvent,
+    /// Sent at startup because the input no longer matches the last reported state.
+    #[serde(default)]
+    pub reconciliation: bool,
+    /// The state before this one and when it was published, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<LastState>,
+}
+
+/// Delivery progress, stored next to the outbox so it survives restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cursors {
+    next_sequence: u64,
+    /// Last sequence number delivered to each endpoint.
+    delivered: HashMap<String, u64>,
+}
+
+struct OutboxState {
+    entries: Vec<OutboxEntry>,
+    cursors: Cursors,
+}
+
+/// Append-only on-disk queue of status events.
+///
+/// Every event is written to disk before any delivery is attempted. Each endpoint keeps its own
+/// cursor, so an endpoint that is down does not hold back the others, and entries are only
+/// dropped from the file once every endpoint has received them, or once more than
+/// `max_entries` are waiting.
+pub struct Outbox {
+    path: PathBuf,
+    cursors_path: PathBuf,
+    endpoints: Vec<String>,
+    max_entries: usize,
+    history: Arc<History>,
+    state: Mutex<OutboxState>,
+    latest: watch::Sender<u64>,
+}
+
+impl Outbox {
+    pub fn open(path: impl AsRef<Path>, endpoints: Vec<String>, max_entries: usize, history: Arc<History>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create outbox directory {}", dir.display()))?;
+        }
+        let cursors_path = path.with_extension("cursors.json");
+
+        let (entries, damaged) = Self::read_entries(&path)?;
+        let mut cursors: Cursors = match fs::read_to_string(&cursors_path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .context(format!("Failed to parse outbox cursors {}", cursors_path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Cursors::default(),
+            Err(e) => return Err(e).context(format!("Failed to read outbox cursors {}", cursors_path.display())),
+        };
+
+        if let Some(last) = entries.last() {
+            cursors.next_sequence = cursors.next_sequence.max(last.sequence + 1);
+        }
+        cursors.next_sequence = cursors.next_sequence.max(1);
+
+        // Endpoints that were added since the last run start from the current position instead
+        // of replaying old events.
+        let latest = cursors.next_sequence - 1;
+        for endpoint in &endpoints {
+            cursors.delivered.entry(endpoint.clone()).or_insert(latest);
+        }
+
+        let pending = entries
+            .iter()
+            .filter(|entry| endpoints.iter().any(|endpoint| cursors.delivered[endpoint] < entry.sequence))
+            .count();
+        if pending > 0 {
+            info!("Outbox has {} undelivered events from a previous run", pending);
+        }
+
+        let (latest, _) = watch::channel(latest);
+        let outbox = Self {
+            path,
+            cursors_path,
+            endpoints,
+            max_entries,
+            history,
+            state: Mutex::new(OutboxState { entries, cursors }),
+            latest,
+        };
+        {
+            let state = outbox.state.lock().unwrap();
+            outbox.persist_cursors(&state.cursors)?;
+            // Otherwise the next event would be appended to the end of the damaged line
+            if damaged {
+                outbox.rewrite_entries(&state.entries)?;
+            }
+        }
+
+        Ok(outbox)
+    }
+
+    /// Durably records an event and wakes up the delivery workers.
+    pub fn append(&self, event: StatusEvent, reconciliation: bool, previous: Option<LastState>) -> Result<OutboxEntry> {
+        let mut state = self.state.lock().unwrap();
+
+        let entry = OutboxEntry {
+            sequence: state.cursors.next_sequence,
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            event,
+            reconciliation,
+            previous,
+        };
+
+        // Nothing to deliver, but the sequence still identifies the event in the history
+        if self.endpoints.is_empty() {
+            state.cursors.next_sequence += 1;
+            self.persist_cursors(&state.cursors)?;
+            return Ok(entry);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("Failed to open outbox {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+
+        state.cursors.next_sequence += 1;
+        state.entries.push(entry.clone());
+
+        // An endpoint that has been down for a long time only misses the oldest events
+        let overflow = state.entries.len().saturating_sub(self.max_entries);
+        let mut dropped = Vec::new();
+        for old in state.entries.drain(..overflow).collect::<Vec<_>>() {
+            for endpoint in &self.endpoints {
+                if state.cursors.delivered[endpoint] < old.sequence {
+                    state.cursors.delivered.insert(endpoint.clone(), old.sequence);
+                    dropped.push((old.sequence, endpoint.clone()));
+                }
+            }
+        }
+        if overflow > 0 {
+            self.persist_cursors(&state.cursors)?;
+            self.rewrite_entries(&state.entries)?;
+        }
+        drop(state);
+
+        for (sequence, endpoint) in dropped {
+            warn!("Outbox is full, dropping event #{} for endpoint '{}'", sequence, endpoint);
+            let error = Some("Outbox is full".to_string());
+            if let Err(e) = self.history.record_delivery(sequence, &endpoint, DeliveryOutcome::Superseded, error) {
+                error!("Failed to record delivery in history: {}", e);
+            }
+        }
+
+        self.latest.send_replace(entry.sequence);
+        Ok(entry)
+    }
+
+    /// Returns the oldest event the endpoint has not received yet.
+    fn next_for(&self, endpoint: &str) -> Option<OutboxEntry> {
+        let state = self.state.lock().unwrap();
+        let delivered = state.cursors.delivered.get(endpoint).copied().unwrap_or(0);
+        state.entries.iter().find(|entry| entry.sequence > delivered).cloned()
+    }
+
+    /// Skips all but the newest of the events waiting for the endpoint, since it only needs to
+    /// know the current state. Returns the skipped events.
+    fn skip_superseded(&self, endpoint: &str) -> Result<Vec<OutboxEntry>> {
+        let skipped: Vec<OutboxEntry> = {
+            let state = self.state.lock().unwrap();
+            let delivered = state.cursors.delivered.get(endpoint).copied().unwrap_or(0);
+            let pending: Vec<&OutboxEntry> = state.entries.iter().filter(|entry| entry.sequence > delivered).collect();
+            match pending.split_last() {
+                Some((_, older)) => older.iter().map(|entry| (*entry).clone()).collect(),
+                None => Vec::new(),
+            }
+        };
+
+        if let Some(last) = skipped.last() {
+            self.acknowledge(endpoint, last.sequence)?;
+        }
+        Ok(skipped)
+    }
+
+    /// Skips superseded events for the endpoint and records that in the history.
+    fn coalesce(&self, endpoint: &str) {
+        match self.skip_superseded(endpoint) {
+            Ok(skipped) => {
+                for entry in skipped {
+                    info!("Skipping superseded event #{} ({}) for endpoint '{}'", entry.sequence, entry.event, endpoint);
+                    if let Err(e) = self.history.record_delivery(entry.sequence, endpoint, DeliveryOutcome::Superseded, None) {
+                        error!("Failed to record delivery in history: {}", e);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to update outbox for endpoint '{}': {}", endpoint, e),
+        }
+    }
+
+    /// Moves the endpoint past an event it is done with, and records the outcome in the history.
+    fn finish(&self, endpoint: &str, sequence: u64, outcome: DeliveryOutcome, error: Option<String>) {
+        if let Err(e) = self.acknowledge(endpoint, sequence) {
+            error!("Failed to update outbox for endpoint '{}': {}", endpoint, e);
+        }
+        if let Err(e) = self.history.record_delivery(sequence, endpoint, outcome, error) {
+            error!("Failed to record delivery in history: {}", e);
+        }
+    }
+
+    /// Marks an event as delivered to the endpoint and compacts the outbox once every endpoint
+    /// has caught up.
+    fn acknowledge(&self, endpoint: &str, sequence: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.cursors.delivered.insert(endpoint.to_string(), sequence);
+        self.persist_cursors(&state.cursors)?;
+
+        let oldest_delivered = self.endpoints
+            .iter()
+            .map(|endpoint| state.cursors.delivered[endpoint])
+            .min()
+            .unwrap_or(u64::MAX);
+
+        if state.entries.first().is_some_and(|entry| entry.sequence <= oldest_delivered) {
+            state.entries.retain(|entry| entry.sequence > oldest_delivered);
+            self.rewrite_entries(&state.entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of events that have not been delivered to every endpoint yet.
+    pub fn pending_count(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.entries
+            .iter()
+            .filter(|entry| self.endpoints.iter().any(|endpoint| state.cursors.delivered[endpoint] < entry.sequence))
+            .count()
+    }
+
+    /// Delivers events to one endpoint in order, retrying until they go through. With `coalesce`,
+    /// an endpoint that has fallen behind is only sent the newest event.
+    ///
+    /// On shutdown the worker finishes the request in progress, without waiting to retry it, and
+    /// stops; anything left stays in the outbox for the next run.
+    pub async fn run_worker(
+        self: Arc<Self>,
+        delivery: Arc<Delivery>,
+        endpoint: String,
+        coalesce: bool,
+        retry_interval: Duration,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let mut latest = self.latest.subscribe();
+
+        while !*shutdown.borrow() {
+            if coalesce {
+                let (outbox, endpoint) = (self.clone(), endpoint.clone());
+                run_blocking(move || outbox.coalesce(&endpoint)).await;
+            }
+
+            let Some(entry) = self.next_for(&endpoint) else {
+                tokio::select! {
+                    changed = latest.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                    _ = shutdown.changed() => return,
+                }
+                continue;
+            };
+
+            match delivery.notify_endpoint(&endpoint, &entry, shutdown.clone()).await {
+                Ok(()) => {
+                    let (outbox, endpoint) = (self.clone(), endpoint.clone());
+                    run_blocking(move || outbox.finish(&endpoint, entry.sequence, DeliveryOutcome::Delivered, None)).await;
+                }
+                Err(NotifyError::Rejected(rejection)) => {
+                    // Retrying would only be rejected again, so skip past this event
+                    warn!("Dropping event #{} ({}) for endpoint '{}'", entry.sequence, entry.event, endpoint);
+                    let (outbox, endpoint, error) = (self.clone(), endpoint.clone(), Some(rejection.to_string()));
+                    run_blocking(move || outbox.finish(&endpoint, entry.sequence, DeliveryOutcome::Rejected, error)).await;
+                }
+                Err(NotifyError::CircuitOpen(retry_in)) => {
+                    if sleep_or_shutdown(retry_in, &mut shutdown).await {
+                        return;
+                    }
+                }
+                Err(NotifyError::ShuttingDown) => {
+                    info!("Event #{} ({}) stays queued for endpoint '{}' until restart", entry.sequence, entry.event, endpoint);
+                    return;
+                }
+                Err(NotifyError::UnknownEndpoint(_)) => {
+                    error!("Endpoint '{}' is not configured, stopping its outbox worker", endpoint);
+                    return;
+                }
+                Err(e) => {
+                    let delay = e.retry_in().map_or(retry_interval, |retry_in| retry_in.max(retry_interval));
+                    warn!(
+                        "Event #{} ({}) is still queued for endpoint '{}', retrying in {}s",
+                        entry.sequence,
+                        entry.event,
+                        endpoint,
+                        delay.as_secs()
+                    );
+                    if sleep_or_shutdown(delay, &mut shutdown).await {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the stored entries, and whether any lines had to be skipped.
+    fn read_entries(path: &Path) -> Result<(Vec<OutboxEntry>, bool)> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), false)),
+            Err(e) => return Err(e).context(format!("Failed to open outbox {}", path.display())),
+        };
+
+        let mut entries = Vec::new();
+        let mut damaged = false;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A power cut in the middle of a write can leave a partial last line behind
+            match serde_json::from_str::<OutboxEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    warn!("Skipping unreadable outbox line {} in {}: {}", index + 1, path.display(), e);
+                    damaged = true;
+                }
+            }
+        }
+
+        Ok((entries, damaged))
+    }
+
+    fn rewrite_entries(&self, entries: &[OutboxEntry]) -> Result<()> {
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(&serde_json::to_string(entry)?);
+            contents.push('\n');
+        }
+        write_atomically(&self.path, contents.as_bytes())
+    }
+
+    fn persist_cursors(&self, cursors: &Cursors) -> Result<()> {
+        write_atomically(&self.cursors_path, serde_json::to_string_pretty(cursors)?.as_bytes())
+    }
+}
+
+/// Sleeps for `duration`, returning true if shutdown was requested in the meantime.
+async fn sleep_or_shutdown(duration: Duration, shutdown: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => false,
+        _ = shutdown.changed() => true,
+    }
+}
+
+/// Runs file work on the blocking thread pool, since syncing to an SD card can hold up a
+/// runtime worker for a long time.
+pub async fn run_blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(work)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Replaces a file by writing a temporary sibling and renaming it over the original.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path).context(format!("Failed to write {}", tmp_path.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path).context(format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    use StatusEvent::{Closed, Open};
+
+    fn open(dir: &TempDir, endpoints: &[&str], max_entries: usize) -> Outbox {
+        let history = Arc::new(History::open(dir.path().join("history.jsonl")).unwrap());
+        let endpoints = endpoints.iter().map(|endpoint| endpoint.to_string()).collect();
+        Outbox::open(dir.path().join("outbox.jsonl"), endpoints, max_entries, history).unwrap()
+    }
+
+    fn sequences(entries: &[OutboxEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.sequence).collect()
+    }
+
+    fn sequences_on_disk(dir: &TempDir) -> Vec<u64> {
+        sequences(&Outbox::read_entries(&dir.path().join("outbox.jsonl")).unwrap().0)
+    }
+
+    #[test]
+    fn recovers_from_truncated_last_line() {
+        let dir = TempDir::new().unwrap();
+        {
+            let outbox = open(&dir, &["a"], 100);
+            outbox.append(Open, false, None).unwrap();
+            outbox.append(Closed, false, None).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(dir.path().join("outbox.jsonl")).unwrap();
+        write!(file, "{{\"sequence\":3,\"id\":").unwrap();
+
+        let outbox = open(&dir, &["a"], 100);
+        assert_eq!(outbox.pending_count(), 2);
+        assert_eq!(outbox.next_for("a").map(|entry| entry.sequence), Some(1));
+        assert_eq!(outbox.append(Open, false, None).unwrap().sequence, 3);
+        // The damaged line is gone, so the new event is readable after the next restart
+        assert_eq!(sequences_on_disk(&dir), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn sequence_keeps_increasing_after_full_compaction() {
+        let dir = TempDir::new().unwrap();
+        {
+            let outbox = open(&dir, &["a"], 100);
+            outbox.append(Open, false, None).unwrap();
+            outbox.append(Closed, false, None).unwrap();
+            outbox.acknowledge("a", 2).unwrap();
+            assert!(sequences_on_disk(&dir).is_empty());
+        }
+
+        let outbox = open(&dir, &["a"], 100);
+        assert_eq!(outbox.append(Open, false, None).unwrap().sequence, 3);
+    }
+
+    #[test]
+    fn new_endpoints_start_at_current_position() {
+        let dir = TempDir::new().unwrap();
+        {
+            let outbox = open(&dir, &["a"], 100);
+            outbox.append(Open, false, None).unwrap();
+            outbox.append(Closed, false, None).unwrap();
+        }
+
+        let outbox = open(&dir, &["a", "b"], 100);
+        assert_eq!(outbox.next_for("a").map(|entry| entry.sequence), Some(1));
+        assert!(outbox.next_for("b").is_none());
+
+        let entry = outbox.append(Open, false, None).unwrap();
+        assert_eq!(outbox.next_for("b").map(|entry| entry.sequence), Some(entry.sequence));
+    }
+
+    #[test]
+    fn acknowledge_compacts_once_every_endpoint_has_caught_up() {
+        let dir = TempDir::new().unwrap();
+        let outbox = open(&dir, &["a", "b"], 100);
+        for status in [Open, Closed, Open] {
+            outbox.append(status, false, None).unwrap();
+        }
+
+        outbox.acknowledge("a", 2).unwrap();
+        assert_eq!(sequences_on_disk(&dir), vec![1, 2, 3]);
+        assert_eq!(outbox.next_for("a").map(|entry| entry.sequence), Some(3));
+
+        outbox.acknowledge("b", 1).unwrap();
+        assert_eq!(sequences_on_disk(&dir), vec![2, 3]);
+        assert_eq!(outbox.pending_count(), 2);
+
+        // The cursors survive a restart
+        drop(outbox);
+        let outbox = open(&dir, &["a", "b"], 100);
+        assert_eq!(outbox.next_for("a").map(|entry| entry.sequence), Some(3));
+        assert_eq!(outbox.next_for("b").map(|entry| entry.sequence), Some(2));
+    }
+
+    #[test]
+    fn skip_superseded_leaves_only_the_newest() {
+        let dir = TempDir::new().unwrap();
+        let outbox = open(&dir, &["a", "b"], 100);
+        for status in [Open, Closed, Open] {
+            outbox.append(status, false, None).unwrap();
+        }
+
+        let skipped = outbox.skip_superseded("a").unwrap();
+        assert_eq!(sequences(&skipped), vec![1, 2]);
+        assert_eq!(outbox.next_for("a").map(|entry| entry.sequence), Some(3));
+        // Other endpoints still get every event
+        assert_eq!(outbox.next_for("b").map(|entry| entry.sequence), Some(1));
+
+        assert!(outbox.skip_superseded("a").unwrap().is_empty());
+    }
+
+    #[test]
+    fn overflow_drops_oldest_and_records_them() {
+        let dir = TempDir::new().unwrap();
+        let outbox = open(&dir, &["a", "b"], 2);
+        outbox.append(Open, false, None).unwrap();
+        outbox.acknowledge("b", 1).unwrap();
+        outbox.append(Closed, false, None).unwrap();
+        outbox.append(Open, false, None).unwrap();
+
+        assert_eq!(sequences_on_disk(&dir), vec![2, 3]);
+        assert_eq!(outbox.next_for("a").map(|entry| entry.sequence), Some(2));
+        assert_eq!(outbox.next_for("b").map(|entry| entry.sequence), Some(2));
+
+        // Only the endpoint that had not received the dropped event gets an outcome for it
+        let history = fs::read_to_string(dir.path().join("history.jsonl")).unwrap();
+        let records: Vec<serde_json::Value> = history.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["sequence"], 1);
+        assert_eq!(records[0]["endpoint"], "a");
+        assert_eq!(records[0]["outcome"], "superseded");
+        assert_eq!(records[0]["error"], "Outbox is full");
+    }
+}
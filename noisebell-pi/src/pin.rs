@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+
+/// Electrical level of an input pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+/// Called on every raw edge, from whatever thread the pin delivers interrupts on.
+pub type EdgeCallback = Box<dyn FnMut(Edge) + Send>;
+
+/// A GPIO input, so the monitor logic does not depend on the hardware it runs on.
+///
+/// Implementations report every edge as it happens, bounces included. Debouncing is up to the
+/// caller.
+pub trait InputPin: Send + Sync {
+    fn read(&self) -> Level;
+    /// Starts reporting edges to `callback`, replacing any previous callback.
+    fn on_edge(&mut self, callback: EdgeCallback) -> Result<()>;
+}
+
+/// A Raspberry Pi GPIO pin, read through rppal with the internal pull-up enabled.
+pub struct RppalPin {
+    pin: rppal::gpio::InputPin,
+}
+
+impl RppalPin {
+    pub fn new(pin_number: u8) -> Result<Self> {
+        let gpio = rppal::gpio::Gpio::new().context("Failed to initialize GPIO")?;
+        let pin = gpio
+            .get(pin_number)
+            .context(format!("Failed to get GPIO pin {}", pin_number))?
+            .into_input_pullup();
+
+        Ok(Self { pin })
+    }
+}
+
+impl InputPin for RppalPin {
+    fn read(&self) -> Level {
+        match self.pin.read() {
+            rppal::gpio::Level::Low => Level::Low,
+            rppal::gpio::Level::High => Level::High,
+        }
+    }
+
+    fn on_edge(&mut self, mut callback: EdgeCallback) -> Result<()> {
+        // rppal calls the handler on its own thread
+        self.pin.set_async_interrupt(rppal::gpio::Trigger::Both, None, move |event| {
+            match event.trigger {
+                rppal::gpio::Trigger::RisingEdge => callback(Edge::Rising),
+                rppal::gpio::Trigger::FallingEdge => callback(Edge::Falling),
+                _ => {} // Ignore other triggers
+            }
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! HMAC-SHA256 request signing, so endpoints can check that a request came from noisebell and
+//! is not a replay of an old one.
+//!
+//! When an endpoint has a signing secret, every request carries three headers:
+//!
+//! - `X-Noisebell-Timestamp`: Unix time in seconds when the request was sent
+//! - `X-Noisebell-Nonce`: 32 random hex characters, new for every request including retries
+//! - `X-Noisebell-Signature`: `sha256=` followed by the hex HMAC-SHA256 of
+//!   `{timestamp}.{nonce}.{body}`, keyed with the secret
+//!
+//! A receiver recomputes the signature over the raw body and compares it in constant time,
+//! rejects timestamps too far from its own clock, and remembers the nonces it has seen for that
+//! long, so a captured request cannot be sent again. [`Verifier`] does all of this.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use thiserror::Error;
+
+pub const TIMESTAMP_HEADER: &str = "X-Noisebell-Timestamp";
+pub const NONCE_HEADER: &str = "X-Noisebell-Nonce";
+pub const SIGNATURE_HEADER: &str = "X-Noisebell-Signature";
+
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+/// How far a request's timestamp may be from the receiver's clock by default.
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(300);
+
+/// Values of the signature headers for one request.
+#[derive(Debug, Clone)]
+pub struct SignatureHeaders {
+    pub timestamp: String,
+    pub nonce: String,
+    pub signature: String,
+}
+
+/// Signs a request body with the current time and a fresh nonce.
+pub fn sign_request(secret: &[u8], body: &[u8]) -> SignatureHeaders {
+    let timestamp = Utc::now().timestamp().to_string();
+    let nonce = hex::encode(rand::rng().random::<[u8; 16]>());
+    let signature = sign(secret, &timestamp, &nonce, body);
+
+    SignatureHeaders {
+        timestamp,
+        nonce,
+        signature,
+    }
+}
+
+/// Returns the `X-Noisebell-Signature` value for a request.
+pub fn sign(secret: &[u8], timestamp: &str, nonce: &str, body: &[u8]) -> String {
+    let mac = mac(secret, timestamp, nonce, body);
+    format!("{}{}", SIGNATURE_PREFIX, hex::encode(mac.finalize().into_bytes()))
+}
+
+fn mac(secret: &[u8], timestamp: &str, nonce: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(nonce.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum VerifyError {
+    #[error("Malformed {0} header")]
+    Malformed(&'static str),
+    #[error("Signature does not match")]
+    Mismatch,
+    #[error("Timestamp is {0}s away from the current time")]
+    Expired(i64),
+    #[error("Nonce was already used")]
+    Replayed,
+}
+
+/// Checks signed requests on the receiving side, including replay protection.
+///
+/// ```
+/// use noisebell::signing::{Verifier, sign_request};
+///
+/// let body = br#"{"status": "open"}"#;
+/// let headers = sign_request(b"secret", body);
+///
+/// let verifier = Verifier::new(b"secret");
+/// assert!(verifier.verify(&headers.timestamp, &headers.nonce, &headers.signature, body).is_ok());
+/// // The same request again is a replay
+/// assert!(verifier.verify(&headers.timestamp, &headers.nonce, &headers.signature, body).is_err());
+/// ```
+pub struct Verifier {
+    secret: Vec<u8>,
+    tolerance: Duration,
+    /// Nonces seen within the tolerance, with their timestamps.
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl Verifier {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            tolerance: DEFAULT_TOLERANCE,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets how far timestamps may be from the current time, which is also how long nonces
+    /// are remembered.
+    pub fn with_tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Verifies a request from its header values and raw body.
+    pub fn verify(&self, timestamp: &str, nonce: &str, signature: &str, body: &[u8]) -> Result<(), VerifyError> {
+        self.verify_at(Utc::now().timestamp(), timestamp, nonce, signature, body)
+    }
+
+    fn verify_at(&self, now: i64, timestamp: &str, nonce: &str, signature: &str, body: &[u8]) -> Result<(), VerifyError> {
+        let sent_at: i64 = timestamp.trim().parse().map_err(|_| VerifyError::Malformed(TIMESTAMP_HEADER))?;
+        if nonce.is_empty() {
+            return Err(VerifyError::Malformed(NONCE_HEADER));
+        }
+        let signature = signature
+            .trim()
+            .strip_prefix(SIGNATURE_PREFIX)
+            .and_then(|signature| hex::decode(signature).ok())
+            .ok_or(VerifyError::Malformed(SIGNATURE_HEADER))?;
+
+        mac(&self.secret, timestamp, nonce, body)
+            .verify_slice(&signature)
+            .map_err(|_| VerifyError::Mismatch)?;
+
+        let tolerance = self.tolerance.as_secs() as i64;
+        if (now - sent_at).abs() > tolerance {
+            return Err(VerifyError::Expired(now - sent_at));
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, seen_at| (now - *seen_at).abs() <= tolerance);
+        if seen.insert(nonce.to_string(), sent_at).is_some() {
+            return Err(VerifyError::Replayed);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const BODY: &[u8] = br#"{"status": "open"}"#;
+    const NOW: i64 = 1_750_000_000;
+
+    fn verifier() -> Verifier {
+        Verifier::new(SECRET).with_tolerance(Duration::from_secs(60))
+    }
+
+    fn verify(verifier: &Verifier, now: i64, sent_at: i64, nonce: &str, signature: &str, body: &[u8]) -> Result<(), VerifyError> {
+        verifier.verify_at(now, &sent_at.to_string(), nonce, signature, body)
+    }
+
+    fn signed(sent_at: i64, nonce: &str) -> String {
+        sign(SECRET, &sent_at.to_string(), nonce, BODY)
+    }
+
+    #[test]
+    fn accepts_signed_request() {
+        let verifier = verifier();
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signed(NOW, "a"), BODY), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tampered_body() {
+        let verifier = verifier();
+        let result = verify(&verifier, NOW, NOW, "a", &signed(NOW, "a"), br#"{"status": "closed"}"#);
+        assert_eq!(result, Err(VerifyError::Mismatch));
+    }
+
+    #[test]
+    fn rejects_wrong_secret() {
+        let verifier = verifier();
+        let signature = sign(b"other secret", &NOW.to_string(), "a", BODY);
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signature, BODY), Err(VerifyError::Mismatch));
+    }
+
+    #[test]
+    fn rejects_signature_for_other_timestamp_or_nonce() {
+        let verifier = verifier();
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signed(NOW - 1, "a"), BODY), Err(VerifyError::Mismatch));
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signed(NOW, "b"), BODY), Err(VerifyError::Mismatch));
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_tolerance() {
+        let verifier = verifier();
+        assert_eq!(verify(&verifier, NOW, NOW - 61, "a", &signed(NOW - 61, "a"), BODY), Err(VerifyError::Expired(61)));
+        assert_eq!(verify(&verifier, NOW, NOW + 61, "b", &signed(NOW + 61, "b"), BODY), Err(VerifyError::Expired(-61)));
+
+        // The edges are still accepted
+        assert_eq!(verify(&verifier, NOW, NOW - 60, "c", &signed(NOW - 60, "c"), BODY), Ok(()));
+        assert_eq!(verify(&verifier, NOW, NOW + 60, "d", &signed(NOW + 60, "d"), BODY), Ok(()));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let verifier = verifier();
+        let signature = signed(NOW, "a");
+        let hex = signature.strip_prefix(SIGNATURE_PREFIX).unwrap();
+        let malformed = Err(VerifyError::Malformed(SIGNATURE_HEADER));
+
+        assert_eq!(verify(&verifier, NOW, NOW, "a", hex, BODY), malformed);
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &format!("sha1={}", hex), BODY), malformed);
+        assert_eq!(verify(&verifier, NOW, NOW, "a", "sha256=not hex", BODY), malformed);
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signature[..signature.len() - 1], BODY), malformed);
+        assert_eq!(verify(&verifier, NOW, NOW, "a", "", BODY), malformed);
+        assert_eq!(verify(&verifier, NOW, NOW, "a", "sha256=", BODY), Err(VerifyError::Mismatch));
+
+        assert_eq!(
+            verifier.verify_at(NOW, "yesterday", "a", &signature, BODY),
+            Err(VerifyError::Malformed(TIMESTAMP_HEADER))
+        );
+        assert_eq!(verify(&verifier, NOW, NOW, "", &signed(NOW, ""), BODY), Err(VerifyError::Malformed(NONCE_HEADER)));
+    }
+
+    #[test]
+    fn rejects_replayed_nonce() {
+        let verifier = verifier();
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signed(NOW, "a"), BODY), Ok(()));
+        assert_eq!(verify(&verifier, NOW + 30, NOW, "a", &signed(NOW, "a"), BODY), Err(VerifyError::Replayed));
+
+        // Also with a fresh timestamp, while the nonce is still remembered
+        assert_eq!(verify(&verifier, NOW + 30, NOW + 30, "a", &signed(NOW + 30, "a"), BODY), Err(VerifyError::Replayed));
+    }
+
+    #[test]
+    fn forgets_nonces_after_tolerance() {
+        let verifier = verifier();
+        assert_eq!(verify(&verifier, NOW, NOW, "a", &signed(NOW, "a"), BODY), Ok(()));
+
+        // By now a replay of the first request would be expired anyway
+        assert_eq!(verify(&verifier, NOW + 61, NOW, "a", &signed(NOW, "a"), BODY), Err(VerifyError::Expired(61)));
+        assert_eq!(verify(&verifier, NOW + 61, NOW + 61, "a", &signed(NOW + 61, "a"), BODY), Ok(()));
+        assert_eq!(verifier.seen.lock().unwrap().len(), 1);
+    }
+}
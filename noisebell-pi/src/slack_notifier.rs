@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde_json::json;
+
+use crate::{
+    config::EndpointConfig,
+    notifier::{self, AttemptError, Notifier, check_response},
+    outbox::OutboxEntry,
+};
+
+/// Posts a message to a Slack channel through an incoming webhook.
+pub struct SlackNotifier {
+    client: Client,
+    webhook_url: String,
+    message_template: String,
+    device_id: String,
+    timeout: Duration,
+}
+
+impl SlackNotifier {
+    pub fn new(config: &EndpointConfig, client: Client, device_id: &str) -> Self {
+        Self {
+            client,
+            webhook_url: config.url.clone(),
+            message_template: config.message_template.clone(),
+            device_id: device_id.to_string(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+
+    fn request(&self, entry: &OutboxEntry) -> RequestBuilder {
+        let payload = json!({
+            "text": notifier::render_message(&self.message_template, entry, &self.device_id),
+        });
+
+        self.client
+            .post(&self.webhook_url)
+            .json(&payload)
+            .timeout(self.timeout)
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    async fn send(&self, entry: &OutboxEntry) -> Result<(), AttemptError> {
+        let response = self.request(entry)
+            .send()
+            .await
+            // The webhook URL is a secret, so keep it out of the logs
+            .map_err(|e| AttemptError::Transport(e.without_url()))?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use crate::StatusEvent;
+
+    fn entry() -> OutboxEntry {
+        OutboxEntry {
+            sequence: 7,
+            id: Uuid::nil(),
+            timestamp: Utc.with_ymd_and_hms(2025, 6, 2, 18, 0, 0).unwrap(),
+            event: StatusEvent::Open,
+            reconciliation: false,
+            previous: None,
+        }
+    }
+
+    #[test]
+    fn posts_text_to_webhook() {
+        let config = EndpointConfig {
+            kind: "slack".to_string(),
+            url: "https://hooks.slack.com/services/T0/B0/secret".to_string(),
+            message_template: "The space is {{status}} ({{device_id}})".to_string(),
+            ..EndpointConfig::default()
+        };
+
+        let request = SlackNotifier::new(&config, Client::new(), "pi").request(&entry()).build().unwrap();
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.url().as_str(), "https://hooks.slack.com/services/T0/B0/secret");
+        assert_eq!(request.headers()["content-type"], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body, json!({ "text": "The space is open (pi)" }));
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::Client;
+use serde::Serialize;
+use tokio::sync::{broadcast, watch};
+use tracing::{error, info};
+
+use crate::{
+    StatusEvent,
+    config::{SpaceApiConfig, SpaceApiLocation},
+    status::{StatusSnapshot, StatusTracker},
+};
+
+/// A [SpaceAPI](https://spaceapi.io) document, compatible with schema versions 14 and 15.
+#[derive(Debug, Serialize)]
+pub struct SpaceApiDocument<'a> {
+    api_compatibility: [&'static str; 2],
+    space: &'a str,
+    logo: &'a str,
+    url: &'a str,
+    location: &'a SpaceApiLocation,
+    contact: &'a BTreeMap<String, String>,
+    /// Left out until the input has been read, since `open` cannot be `null`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<SpaceState>,
+}
+
+#[derive(Debug, Serialize)]
+struct SpaceState {
+    open: bool,
+    /// Unix timestamp of the last change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lastchange: Option<i64>,
+}
+
+pub fn document<'a>(config: &'a SpaceApiConfig, status: &StatusSnapshot) -> SpaceApiDocument<'a> {
+    SpaceApiDocument {
+        api_compatibility: ["14", "15"],
+        space: &config.space,
+        logo: &config.logo,
+        url: &config.url,
+        location: &config.location,
+        contact: &config.contact,
+        state: status.status.map(|current| SpaceState {
+            open: current == StatusEvent::Open,
+            lastchange: status.last_changed.map(|last_changed| last_changed.timestamp()),
+        }),
+    }
+}
+
+/// Sends the document to the configured push URL at startup and after every state change.
+///
+/// A failed push is logged and not retried, since the next change sends a fresh document anyway.
+pub async fn run_pusher(config: SpaceApiConfig, tracker: Arc<StatusTracker>, mut shutdown: watch::Receiver<bool>) {
+    let Some(push_url) = config.push_url.clone() else {
+        return;
+    };
+
+    let client = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap_or_default();
+
+    let mut updates = tracker.subscribe();
+    loop {
+        if let Err(e) = push(&client, &config, &push_url, &tracker.snapshot()).await {
+            error!("Failed to push SpaceAPI document to {}: {}", push_url, e);
+        }
+
+        tokio::select! {
+            update = updates.recv() => {
+                // After lagging, the next push still sends the latest state
+                if let Err(broadcast::error::RecvError::Closed) = update {
+                    return;
+                }
+            }
+            _ = shutdown.changed() => return,
+        }
+    }
+}
+
+async fn push(client: &Client, config: &SpaceApiConfig, push_url: &str, status: &StatusSnapshot) -> Result<()> {
+    let mut request = client.post(push_url).json(&document(config, status));
+    if let Some(api_key) = &config.push_api_key {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+
+    request.send().await?.error_for_status()?;
+    info!("Pushed SpaceAPI document to {}", push_url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use serde_json::{Value, json};
+
+    fn snapshot(status: Option<StatusEvent>) -> StatusSnapshot {
+        let started_at = Utc.with_ymd_and_hms(2025, 6, 2, 0, 0, 0).unwrap();
+        StatusSnapshot {
+            status,
+            last_changed: status.map(|_| started_at),
+            started_at,
+            uptime_secs: 0,
+        }
+    }
+
+    fn serialize(status: Option<StatusEvent>) -> Value {
+        let config = SpaceApiConfig {
+            space: "Noisebridge".to_string(),
+            ..Default::default()
+        };
+        serde_json::to_value(document(&config, &snapshot(status))).unwrap()
+    }
+
+    #[test]
+    fn includes_known_state() {
+        let document = serialize(Some(StatusEvent::Open));
+        assert_eq!(document["space"], "Noisebridge");
+        assert_eq!(document["state"], json!({"open": true, "lastchange": 1748822400}));
+
+        assert_eq!(serialize(Some(StatusEvent::Closed))["state"]["open"], false);
+    }
+
+    #[test]
+    fn leaves_out_unknown_state() {
+        let document = serialize(None);
+        assert!(document.get("state").is_none());
+        assert_eq!(document["api_compatibility"], json!(["14", "15"]));
+    }
+}
@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{StatusEvent, outbox::write_atomically};
+
+/// The last state that was published, as stored on disk.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LastState {
+    pub status: StatusEvent,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Remembers the last published state across restarts.
+///
+/// On startup it is compared with the input, so a change made while noisebell was not running
+/// is still reported.
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create state directory {}", dir.display()))?;
+        }
+
+        Ok(Self { path })
+    }
+
+    /// Returns the stored state, or `None` if nothing has been published yet.
+    pub fn load(&self) -> Result<Option<LastState>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .context(format!("Failed to parse last state {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("Failed to read last state {}", self.path.display())),
+        }
+    }
+
+    pub fn save(&self, state: &LastState) -> Result<()> {
+        write_atomically(&self.path, serde_json::to_string_pretty(state)?.as_bytes())
+    }
+}
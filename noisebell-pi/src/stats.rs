@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+
+use std::fmt::Display;
+
+use chrono::{DateTime, Datelike, Offset, TimeDelta, TimeZone, Timelike, Utc};
+use serde::Serialize;
+
+use crate::{StatusEvent, history::HistoryEvent};
+
+/// A stretch of time during which the state did not change.
+#[derive(Debug, Clone, Copy)]
+struct Period {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    status: StatusEvent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Closure {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_secs: i64,
+    /// The space is still closed, so `end` is the end of the range.
+    pub ongoing: bool,
+}
+
+/// Open-hours statistics over a time range.
+///
+/// Days, weeks and hours of the week are in the time zone given to [`compute`]. Periods that
+/// cross the edges of the range are cut off at them.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub from: Option<DateTime<Utc>>,
+    pub to: DateTime<Utc>,
+    pub total_open_secs: i64,
+    /// Open time per day (`2025-06-01`), ISO week (`2025-W22`) and month (`2025-06`).
+    pub open_secs_per_day: BTreeMap<String, i64>,
+    pub open_secs_per_week: BTreeMap<String, i64>,
+    pub open_secs_per_month: BTreeMap<String, i64>,
+    pub open_sessions: usize,
+    pub average_session_secs: Option<i64>,
+    pub longest_closure: Option<Closure>,
+    /// Share of the time the space was open in each hour of the week, indexed by weekday
+    /// (Monday first) and then hour. `None` where that hour was never observed.
+    pub open_probability: Vec<Vec<Option<f64>>>,
+}
+
+/// Computes statistics for `[from, to)` from the recorded transitions, oldest first.
+///
+/// Events before `from` are needed to know the state at the start of the range. The last
+/// state is assumed to hold until `to`, including while noisebell was not running. Days and
+/// hours are counted in `zone`.
+pub fn compute<Tz: TimeZone>(events: &[HistoryEvent], from: Option<DateTime<Utc>>, to: DateTime<Utc>, zone: &Tz) -> Stats
+where
+    Tz::Offset: Display,
+{
+    let periods = periods(events, from, to);
+
+    let mut stats = Stats {
+        from,
+        to,
+        total_open_secs: 0,
+        open_secs_per_day: BTreeMap::new(),
+        open_secs_per_week: BTreeMap::new(),
+        open_secs_per_month: BTreeMap::new(),
+        open_sessions: 0,
+        average_session_secs: None,
+        longest_closure: None,
+        open_probability: Vec::new(),
+    };
+
+    // Seconds observed and seconds open for every hour of the week
+    let mut observed = [[0i64; 24]; 7];
+    let mut open = [[0i64; 24]; 7];
+
+    for period in &periods {
+        let duration_secs = (period.end - period.start).num_seconds();
+
+        match period.status {
+            StatusEvent::Open => {
+                stats.total_open_secs += duration_secs;
+                stats.open_sessions += 1;
+            }
+            StatusEvent::Closed => {
+                if stats.longest_closure.as_ref().is_none_or(|longest| duration_secs > longest.duration_secs) {
+                    stats.longest_closure = Some(Closure {
+                        start: period.start,
+                        end: period.end,
+                        duration_secs,
+                        ongoing: period.end == to,
+                    });
+                }
+            }
+        }
+
+        // Walk the period an hour at a time so every slice falls in one hour of the zone
+        let mut start = period.start;
+        while start < period.end {
+            let end = next_hour(start, zone).min(period.end);
+            let secs = (end - start).num_seconds();
+            let local = start.with_timezone(zone);
+            let (weekday, hour) = (local.weekday().num_days_from_monday() as usize, local.hour() as usize);
+
+            observed[weekday][hour] += secs;
+            if period.status == StatusEvent::Open {
+                open[weekday][hour] += secs;
+                *stats.open_secs_per_day.entry(local.format("%Y-%m-%d").to_string()).or_default() += secs;
+                *stats.open_secs_per_week.entry(local.format("%G-W%V").to_string()).or_default() += secs;
+                *stats.open_secs_per_month.entry(local.format("%Y-%m").to_string()).or_default() += secs;
+            }
+
+            start = end;
+        }
+    }
+
+    if stats.open_sessions > 0 {
+        stats.average_session_secs = Some(stats.total_open_secs / stats.open_sessions as i64);
+    }
+
+    stats.open_probability = (0..7)
+        .map(|weekday| {
+            (0..24)
+                .map(|hour| {
+                    let observed = observed[weekday][hour];
+                    (observed > 0).then(|| open[weekday][hour] as f64 / observed as f64)
+                })
+                .collect()
+        })
+        .collect();
+
+    stats
+}
+
+/// Turns transitions into periods of unchanged state, clipped to `[from, to)`.
+fn periods(events: &[HistoryEvent], from: Option<DateTime<Utc>>, to: DateTime<Utc>) -> Vec<Period> {
+    let mut periods: Vec<Period> = Vec::new();
+
+    for (index, event) in events.iter().enumerate() {
+        let end = events.get(index + 1).map_or(to, |next| next.timestamp).min(to);
+        let start = from.map_or(event.timestamp, |from| event.timestamp.max(from));
+        if start >= end {
+            continue;
+        }
+
+        // Repeated states, such as a reconciliation that matched, extend the current period
+        match periods.last_mut() {
+            Some(last) if last.status == event.status && last.end == start => last.end = end,
+            _ => periods.push(Period {
+                start,
+                end,
+                status: event.status,
+            }),
+        }
+    }
+
+    periods
+}
+
+/// Start of the hour in `zone` after `time`. Works for zones with half-hour offsets too.
+fn next_hour<Tz: TimeZone>(time: DateTime<Utc>, zone: &Tz) -> DateTime<Utc> {
+    let offset = time.with_timezone(zone).offset().fix().local_minus_utc() as i64;
+    let into_hour = (time.timestamp() + offset).rem_euclid(3600);
+    let truncated = time - TimeDelta::nanoseconds(time.nanosecond() as i64) - TimeDelta::seconds(into_hour);
+    truncated + TimeDelta::hours(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+    use uuid::Uuid;
+
+    use StatusEvent::{Closed, Open};
+
+    const HOUR: i64 = 3600;
+
+    /// Central European Summer Time, two hours ahead of UTC.
+    fn zone() -> FixedOffset {
+        FixedOffset::east_opt(2 * HOUR as i32).unwrap()
+    }
+
+    /// `hours` after midnight UTC on 2 June 2025, a Monday.
+    fn at(hours: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 2, 0, 0, 0).unwrap() + TimeDelta::hours(hours)
+    }
+
+    fn events(transitions: &[(i64, StatusEvent)]) -> Vec<HistoryEvent> {
+        transitions
+            .iter()
+            .enumerate()
+            .map(|(index, (hours, status))| HistoryEvent {
+                sequence: index as u64 + 1,
+                id: Uuid::new_v4(),
+                timestamp: at(*hours),
+                status: *status,
+                source: "gpio".to_string(),
+                reconciliation: false,
+                inputs: BTreeMap::new(),
+                deliveries: BTreeMap::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn periods_are_clipped_to_the_range() {
+        let events = events(&[(0, Open), (10, Closed), (20, Open)]);
+        let stats = compute(&events, Some(at(5)), at(22), &zone());
+
+        assert_eq!(stats.total_open_secs, 7 * HOUR);
+        assert_eq!(stats.open_sessions, 2);
+        assert_eq!(stats.average_session_secs, Some(7 * HOUR / 2));
+
+        let closure = stats.longest_closure.unwrap();
+        assert_eq!((closure.start, closure.end), (at(10), at(20)));
+        assert_eq!(closure.duration_secs, 10 * HOUR);
+        assert!(!closure.ongoing);
+    }
+
+    #[test]
+    fn state_before_the_range_carries_into_it() {
+        let events = events(&[(-30, Open)]);
+        let stats = compute(&events, Some(at(0)), at(2), &zone());
+
+        assert_eq!(stats.total_open_secs, 2 * HOUR);
+        assert_eq!(stats.open_sessions, 1);
+    }
+
+    #[test]
+    fn events_after_the_range_are_ignored() {
+        let events = events(&[(0, Closed), (5, Open), (8, Closed)]);
+        let stats = compute(&events, None, at(4), &zone());
+
+        assert_eq!(stats.total_open_secs, 0);
+        assert_eq!(stats.open_sessions, 0);
+        assert_eq!(stats.average_session_secs, None);
+    }
+
+    #[test]
+    fn repeated_states_are_one_session() {
+        // A reconciliation that matched the last state does not start a new session
+        let events = events(&[(0, Open), (1, Open), (3, Closed), (4, Closed)]);
+        let stats = compute(&events, None, at(6), &zone());
+
+        assert_eq!(stats.total_open_secs, 3 * HOUR);
+        assert_eq!(stats.open_sessions, 1);
+        assert_eq!(stats.longest_closure.unwrap().duration_secs, 3 * HOUR);
+    }
+
+    #[test]
+    fn closure_still_going_at_the_end_is_ongoing() {
+        let events = events(&[(0, Closed), (1, Open), (2, Closed)]);
+        let stats = compute(&events, None, at(10), &zone());
+
+        let closure = stats.longest_closure.unwrap();
+        assert_eq!((closure.start, closure.end), (at(2), at(10)));
+        assert!(closure.ongoing);
+    }
+
+    #[test]
+    fn open_time_is_split_into_hours_across_days() {
+        let events = events(&[(0, Open)]);
+        let stats = compute(&events, None, at(48), &zone());
+
+        assert_eq!(stats.total_open_secs, 48 * HOUR);
+        assert_eq!(stats.open_secs_per_day.values().sum::<i64>(), 48 * HOUR);
+        assert_eq!(stats.open_secs_per_week.values().sum::<i64>(), 48 * HOUR);
+        assert_eq!(stats.open_secs_per_month.values().sum::<i64>(), 48 * HOUR);
+        // Midnight UTC is two in the morning in the zone
+        let days: Vec<(&str, i64)> = stats.open_secs_per_day.iter().map(|(day, secs)| (day.as_str(), *secs)).collect();
+        assert_eq!(days, vec![("2025-06-02", 22 * HOUR), ("2025-06-03", 24 * HOUR), ("2025-06-04", 2 * HOUR)]);
+
+        // Every observed hour of the week was open the whole time
+        let observed: Vec<f64> = stats.open_probability.iter().flatten().flatten().copied().collect();
+        assert_eq!(observed.len(), 48);
+        assert!(observed.iter().all(|probability| *probability == 1.0));
+        assert_eq!(stats.open_probability[0][2], Some(1.0));
+        assert_eq!(stats.open_probability[0][1], None);
+    }
+
+    #[test]
+    fn open_probability_is_the_share_of_time_open() {
+        // Open for the first half of every hour of one day
+        let mut transitions = Vec::new();
+        for hour in 0..24 {
+            transitions.push((hour, Open));
+        }
+        let mut events = events(&transitions);
+        for hour in 0..24 {
+            let mut closed = events[hour as usize].clone();
+            closed.timestamp = at(hour) + TimeDelta::minutes(30);
+            closed.status = Closed;
+            events.push(closed);
+        }
+        events.sort_by_key(|event| event.timestamp);
+
+        let stats = compute(&events, None, at(24), &zone());
+        let observed: Vec<f64> = stats.open_probability.iter().flatten().flatten().copied().collect();
+        assert_eq!(stats.total_open_secs, 12 * HOUR);
+        assert_eq!(stats.open_sessions, 24);
+        assert_eq!(observed.len(), 24);
+        assert!(observed.iter().all(|probability| *probability == 0.5));
+    }
+
+    #[test]
+    fn no_events_means_nothing_observed() {
+        let stats = compute(&[], None, at(0), &zone());
+
+        assert_eq!(stats.total_open_secs, 0);
+        assert!(stats.longest_closure.is_none());
+        assert_eq!(stats.open_probability.len(), 7);
+        assert!(stats.open_probability.iter().flatten().all(Option::is_none));
+    }
+
+    #[test]
+    fn next_hour_is_on_the_hour() {
+        let time = at(5) + TimeDelta::minutes(17) + TimeDelta::milliseconds(250);
+        assert_eq!(next_hour(time, &zone()), at(6));
+
+        // A time already on the hour moves on to the next one
+        assert_eq!(next_hour(at(6), &zone()), at(7));
+    }
+
+    #[test]
+    fn next_hour_follows_half_hour_offsets() {
+        // 10:47 in India is followed by 11:00, which is 05:30 UTC
+        let india = FixedOffset::east_opt(5 * HOUR as i32 + 30 * 60).unwrap();
+        let time = at(5) + TimeDelta::minutes(17);
+        assert_eq!(next_hour(time, &india), at(5) + TimeDelta::minutes(30));
+    }
+}
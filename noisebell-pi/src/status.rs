@@ -0,0 +1,85 @@
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::StatusEvent;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusSnapshot {
+    pub status: Option<StatusEvent>,
+    pub last_changed: Option<DateTime<Utc>>,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: i64,
+}
+
+struct TrackerState {
+    current: Option<StatusEvent>,
+    last_changed: Option<DateTime<Utc>>,
+}
+
+/// Published switch state, shared by everything that reports on it.
+///
+/// Every recorded change is also broadcast, so the web monitor can push it to connected clients.
+pub struct StatusTracker {
+    started_at: DateTime<Utc>,
+    state: RwLock<TrackerState>,
+    updates: broadcast::Sender<StatusEvent>,
+}
+
+impl StatusTracker {
+    pub fn new() -> Self {
+        let (updates, _) = broadcast::channel(16);
+
+        Self {
+            started_at: Utc::now(),
+            state: RwLock::new(TrackerState {
+                current: None,
+                last_changed: None,
+            }),
+            updates,
+        }
+    }
+
+    /// Sets the state read from the input at startup, which is not a transition.
+    ///
+    /// `last_changed` is when that state was last published, if known from a previous run.
+    pub fn set_initial(&self, status: StatusEvent, last_changed: Option<DateTime<Utc>>) {
+        let mut state = self.state.write().unwrap();
+        if state.current.is_none() {
+            state.current = Some(status);
+            state.last_changed = last_changed;
+        }
+    }
+
+    pub fn record(&self, status: StatusEvent) {
+        {
+            let mut state = self.state.write().unwrap();
+            state.current = Some(status);
+            state.last_changed = Some(Utc::now());
+        }
+
+        // Sending only fails when nobody is listening
+        let _ = self.updates.send(status);
+    }
+
+    pub fn current(&self) -> Option<StatusEvent> {
+        self.state.read().unwrap().current
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StatusEvent> {
+        self.updates.subscribe()
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        let state = self.state.read().unwrap();
+
+        StatusSnapshot {
+            status: state.current,
+            last_changed: state.last_changed,
+            started_at: self.started_at,
+            uptime_secs: (Utc::now() - self.started_at).num_seconds(),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/// Replaces every `{{name}}` in `template` with the matching variable.
+///
+/// Unknown variables are left untouched, so a typo shows up in the output instead of
+/// silently disappearing.
+pub fn render(template: &str, variables: &[(&str, String)]) -> String {
+    render_escaped(template, variables, |value| value.to_string())
+}
+
+/// Like [`render`], but passes every value through `escape` first, so it can be embedded in a
+/// JSON string or a form-encoded body.
+pub fn render_escaped(template: &str, variables: &[(&str, String)], escape: impl Fn(&str) -> String) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let Some(end) = after.find("}}") else {
+            output.push_str(&rest[start..]);
+            return output;
+        };
+
+        let name = after[..end].trim();
+        match variables.iter().find(|(variable, _)| *variable == name) {
+            Some((_, value)) => output.push_str(&escape(value)),
+            None => output.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Formats a duration in seconds for people, such as `3h 12m` or `2d 4h`.
+pub fn human_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Escapes a value for use inside a JSON string, without the surrounding quotes.
+pub fn json_escape(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Percent-encodes a value for use in a form-encoded body.
+pub fn form_escape(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Vec<(&'static str, String)> {
+        vec![("status", "open".to_string()), ("name", "a \"b\" \\ c\nd & e=f".to_string())]
+    }
+
+    #[test]
+    fn replaces_variables() {
+        assert_eq!(render("The space is {{status}}, {{ status }}!", &variables()), "The space is open, open!");
+    }
+
+    #[test]
+    fn keeps_unknown_variables() {
+        assert_eq!(render("{{status}} {{stauts}}", &variables()), "open {{stauts}}");
+    }
+
+    #[test]
+    fn keeps_unterminated_braces() {
+        assert_eq!(render("{{status}} {{status", &variables()), "open {{status");
+        assert_eq!(render("{{status}} }} {", &variables()), "open }} {");
+    }
+
+    #[test]
+    fn json_escapes_values() {
+        let rendered = render_escaped(r#"{"name": "{{name}}"}"#, &variables(), json_escape);
+        assert_eq!(rendered, r#"{"name": "a \"b\" \\ c\nd & e=f"}"#);
+
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed["name"], "a \"b\" \\ c\nd & e=f");
+    }
+
+    #[test]
+    fn form_escapes_values() {
+        let rendered = render_escaped("name={{name}}&status={{status}}", &variables(), form_escape);
+        assert_eq!(rendered, "name=a+%22b%22+%5C+c%0Ad+%26+e%3Df&status=open");
+    }
+
+    #[test]
+    fn escapes_only_values() {
+        assert_eq!(render_escaped("\"{{status}}\" & {{x}}", &variables(), json_escape), "\"open\" & {{x}}");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(human_duration(-5), "0s");
+        assert_eq!(human_duration(59), "59s");
+        assert_eq!(human_duration(125), "2m");
+        assert_eq!(human_duration(3 * 3600 + 12 * 60), "3h 12m");
+        assert_eq!(human_duration(2 * 86400 + 4 * 3600 + 59), "2d 4h");
+    }
+}
@@ -1,176 +1,54 @@
 use std::sync::Arc;
 use anyhow::Result;
-use axum::{
-    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State},
-    response::{Html, IntoResponse},
-    routing::{get},
-    Router,
-};
-use serde::{Deserialize, Serialize};
-use tokio::sync::{RwLock, Mutex};
-use tracing::{info, error};
-use futures_util::{sink::SinkExt, stream::StreamExt};
-use tower_http::services::ServeDir;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::sync::{mpsc, RwLock};
+use tracing::info;
 
 use crate::{StatusEvent, monitor::Monitor};
 
-#[derive(Clone)]
+/// Input driven by the switch on the web page, for testing without physical hardware.
+///
+/// The web server forwards the page's state changes through `manual_changes`.
 pub struct WebMonitor {
-    port: u16,
     current_state: Arc<RwLock<StatusEvent>>,
-    callback: Arc<Mutex<Option<Box<dyn FnMut(StatusEvent) + Send + 'static>>>>,
-}
-
-#[derive(Clone)]
-struct AppState {
-    current_state: Arc<RwLock<StatusEvent>>,
-    callback: Arc<Mutex<Option<Box<dyn FnMut(StatusEvent) + Send + 'static>>>>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct StateChangeMessage {
-    event: String,
-    state: String,
+    manual_changes: Option<mpsc::UnboundedReceiver<StatusEvent>>,
 }
 
 impl WebMonitor {
-    pub fn new(port: u16) -> Result<Self> {
+    /// Starts in `initial_state`, normally the last state published before a restart.
+    pub fn new(manual_changes: mpsc::UnboundedReceiver<StatusEvent>, initial_state: StatusEvent) -> Result<Self> {
         Ok(Self {
-            port,
-            current_state: Arc::new(RwLock::new(StatusEvent::Closed)), // Default to closed
-            callback: Arc::new(Mutex::new(None)),
+            current_state: Arc::new(RwLock::new(initial_state)),
+            manual_changes: Some(manual_changes),
         })
     }
-
-    async fn serve_html() -> impl IntoResponse {
-        Html(include_str!("../static/monitor.html"))
-    }
-
-    async fn websocket_handler(
-        ws: WebSocketUpgrade,
-        State(state): State<AppState>,
-    ) -> impl IntoResponse {
-        ws.on_upgrade(move |socket| Self::handle_websocket(socket, state))
-    }
-
-    async fn handle_websocket(socket: WebSocket, state: AppState) {
-        let (mut sender, mut receiver) = socket.split();
-
-        // Send current state immediately
-        let current_state = *state.current_state.read().await;
-        let initial_message = StateChangeMessage {
-            event: "state_update".to_string(),
-            state: current_state.to_string(),
-        };
-        
-        if let Ok(msg) = serde_json::to_string(&initial_message) {
-            if let Err(e) = sender.send(Message::Text(msg.into())).await {
-                error!("Failed to send initial state: {}", e);
-                return;
-            }
-        }
-
-        // Handle incoming messages from client
-        let state_for_receiver = state.clone();
-        while let Some(msg) = receiver.next().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    let text_str = text.to_string();
-                    if let Ok(state_msg) = serde_json::from_str::<StateChangeMessage>(&text_str) {
-                        if state_msg.event == "state_change" {
-                            let new_state = match state_msg.state.as_str() {
-                                "open" => StatusEvent::Open,
-                                "closed" => StatusEvent::Closed,
-                                _ => continue,
-                            };
-
-                            // Update current state
-                            {
-                                let mut current = state_for_receiver.current_state.write().await;
-                                *current = new_state;
-                            }
-
-                            // Trigger callback
-                            {
-                                let mut callback_guard = state_for_receiver.callback.lock().await;
-                                if let Some(ref mut callback) = callback_guard.as_mut() {
-                                    callback(new_state);
-                                }
-                            }
-
-                            info!("Web monitor state changed to: {:?}", new_state);
-                        }
-                    }
-                }
-                Ok(Message::Close(_)) => {
-                    info!("WebSocket connection closed");
-                    break;
-                }
-                Err(e) => {
-                    error!("WebSocket error: {}", e);
-                    break;
-                }
-                _ => {}
-            }
-        }
-    }
-
-    async fn start_server(&self) -> Result<()> {
-        let app_state = AppState {
-            current_state: self.current_state.clone(),
-            callback: self.callback.clone(),
-        };
-
-        let app = Router::new()
-            .route("/", get(Self::serve_html))
-            .route("/ws", get(Self::websocket_handler))
-            .nest_service("/media", ServeDir::new("media"))
-            .with_state(app_state);
-
-        let addr = format!("0.0.0.0:{}", self.port);
-        info!("Starting web monitor server on {}", addr);
-        
-        let listener = tokio::net::TcpListener::bind(&addr).await?;
-        axum::serve(listener, app).await?;
-        
-        Ok(())
-    }
 }
 
+#[async_trait]
 impl Monitor for WebMonitor {
-    fn monitor(&mut self, callback: Box<dyn FnMut(StatusEvent) + Send>) -> Result<()> {
-        // Store the callback synchronously to ensure it's available immediately
-        let callback_arc = self.callback.clone();
-        let rt = tokio::runtime::Handle::current();
-        tokio::task::block_in_place(|| {
-            rt.block_on(async {
-                let mut guard = callback_arc.lock().await;
-                *guard = Some(callback);
-            });
-        });
-
-        // Run the web server in a blocking task to avoid runtime conflicts
-        let server = self.clone();
-        tokio::task::spawn_blocking(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            if let Err(e) = rt.block_on(server.start_server()) {
-                error!("Web monitor server error: {}", e);
+    fn events(&mut self) -> Result<BoxStream<'static, StatusEvent>> {
+        let manual_changes = self.manual_changes
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Web monitor is already running"))?;
+        let current_state = self.current_state.clone();
+
+        let events = stream::unfold(manual_changes, |mut manual_changes| async move {
+            manual_changes.recv().await.map(|new_state| (new_state, manual_changes))
+        })
+        .then(move |new_state| {
+            let current_state = current_state.clone();
+            async move {
+                *current_state.write().await = new_state;
+                info!("Web monitor state changed to: {:?}", new_state);
+                new_state
             }
         });
 
-        loop {
-            std::thread::sleep(std::time::Duration::from_secs(1));
-        }
+        Ok(events.boxed())
     }
 
-    fn get_current_state(&self) -> StatusEvent {
-        // This is a synchronous function, but we need to read async state
-        // We'll use a blocking operation here similar to how GPIO reads work
-        let rt = tokio::runtime::Handle::current();
-        tokio::task::block_in_place(|| {
-            rt.block_on(async {
-                *self.current_state.read().await
-            })
-        })
+    async fn current_state(&self) -> StatusEvent {
+        *self.current_state.read().await
     }
-}
\ No newline at end of file
+}
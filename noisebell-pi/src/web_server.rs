@@ -0,0 +1,340 @@
+use std::sync::Arc;
+use anyhow::Result;
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
+    routing::{get},
+    Router,
+};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{info, error, warn};
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use tower_http::services::ServeDir;
+
+use crate::{
+    StatusEvent,
+    config::SpaceApiConfig,
+    delivery::{Delivery, NotificationResult},
+    history::{self, History},
+    inputs::{InputState, InputStates},
+    spaceapi,
+    stats,
+    status::{StatusSnapshot, StatusTracker},
+};
+
+/// HTTP server for the monitor page and the local API.
+///
+/// It runs alongside the inputs and shows the published state. Switch changes
+/// made on the page are forwarded to `manual_changes`, or ignored when manual control is off.
+pub struct WebServer {
+    port: u16,
+    state: AppState,
+}
+
+#[derive(Clone)]
+struct AppState {
+    delivery: Arc<Delivery>,
+    /// Published state changes from any source, pushed to every connected client.
+    tracker: Arc<StatusTracker>,
+    history: Arc<History>,
+    inputs: Arc<InputStates>,
+    /// Space metadata for `/spaceapi.json`, when SpaceAPI is enabled.
+    spaceapi: Option<Arc<SpaceApiConfig>>,
+    manual_changes: Option<mpsc::UnboundedSender<StatusEvent>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateChangeMessage {
+    event: String,
+    state: String,
+    /// Whether the page may change the state, sent along with every update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manual_control: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    #[serde(flatten)]
+    status: StatusSnapshot,
+    last_notification: Option<NotificationResult>,
+    /// The state of each input the published state is derived from.
+    inputs: Vec<InputState>,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl WebServer {
+    pub fn new(
+        port: u16,
+        delivery: Arc<Delivery>,
+        tracker: Arc<StatusTracker>,
+        history: Arc<History>,
+        inputs: Arc<InputStates>,
+        spaceapi: Option<Arc<SpaceApiConfig>>,
+        manual_changes: Option<mpsc::UnboundedSender<StatusEvent>>,
+    ) -> Self {
+        Self {
+            port,
+            state: AppState {
+                delivery,
+                tracker,
+                history,
+                inputs,
+                spaceapi,
+                manual_changes,
+            },
+        }
+    }
+
+    /// Serves until `shutdown` is set.
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+        let app = Router::new()
+            .route("/", get(Self::serve_html))
+            .route("/ws", get(Self::websocket_handler))
+            .route("/api/status", get(Self::status_handler))
+            .route("/api/history", get(Self::history_handler))
+            .route("/api/history/export", get(Self::export_handler))
+            .route("/api/stats", get(Self::stats_handler))
+            .route("/stats", get(Self::serve_stats_html))
+            .route("/spaceapi.json", get(Self::spaceapi_handler))
+            .route("/api/endpoints", get(Self::endpoints_handler))
+            .nest_service("/media", ServeDir::new("media"))
+            .with_state(self.state);
+
+        let addr = format!("0.0.0.0:{}", self.port);
+        info!("Starting web server on {}", addr);
+
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move {
+                let _ = shutdown.changed().await;
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn serve_html() -> impl IntoResponse {
+        Html(include_str!("../static/monitor.html"))
+    }
+
+    async fn serve_stats_html() -> impl IntoResponse {
+        Html(include_str!("../static/stats.html"))
+    }
+
+    async fn endpoints_handler(State(state): State<AppState>) -> impl IntoResponse {
+        Json(state.delivery.health())
+    }
+
+    async fn status_handler(State(state): State<AppState>) -> impl IntoResponse {
+        Json(StatusResponse {
+            status: state.tracker.snapshot(),
+            last_notification: state.delivery.last_result(),
+            inputs: state.inputs.snapshot(),
+        })
+    }
+
+    async fn history_handler(
+        State(state): State<AppState>,
+        Query(query): Query<HistoryQuery>,
+    ) -> Response {
+        match state.history.query(query.from, query.to) {
+            Ok(events) => {
+                let newest_first: Vec<_> = events.into_iter().rev().take(query.limit.unwrap_or(usize::MAX)).collect();
+                Json(newest_first).into_response()
+            }
+            Err(e) => Self::history_error(e),
+        }
+    }
+
+    async fn export_handler(
+        State(state): State<AppState>,
+        Query(query): Query<ExportQuery>,
+    ) -> Response {
+        let events = match state.history.query(query.from, query.to) {
+            Ok(events) => events,
+            Err(e) => return Self::history_error(e),
+        };
+
+        match query.format {
+            ExportFormat::Json => (
+                [(header::CONTENT_DISPOSITION, "attachment; filename=\"noisebell-history.json\"")],
+                Json(events),
+            )
+                .into_response(),
+            ExportFormat::Csv => (
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                    (header::CONTENT_DISPOSITION, "attachment; filename=\"noisebell-history.csv\""),
+                ],
+                history::to_csv(&events),
+            )
+                .into_response(),
+        }
+    }
+
+    async fn stats_handler(
+        State(state): State<AppState>,
+        Query(query): Query<StatsQuery>,
+    ) -> Response {
+        let to = query.to.unwrap_or_else(Utc::now);
+
+        // Earlier events are needed to know the state at the start of the range
+        match state.history.query(None, Some(to)) {
+            Ok(events) => Json(stats::compute(&events, query.from, to, &Local)).into_response(),
+            Err(e) => Self::history_error(e),
+        }
+    }
+
+    async fn spaceapi_handler(State(state): State<AppState>) -> Response {
+        let Some(config) = &state.spaceapi else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+
+        // SpaceAPI clients are usually web pages on other sites
+        (
+            [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+            Json(spaceapi::document(config, &state.tracker.snapshot())),
+        )
+            .into_response()
+    }
+
+    fn history_error(e: anyhow::Error) -> Response {
+        error!("Failed to read history: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read history").into_response()
+    }
+
+    async fn websocket_handler(
+        ws: WebSocketUpgrade,
+        State(state): State<AppState>,
+    ) -> impl IntoResponse {
+        ws.on_upgrade(move |socket| Self::handle_websocket(socket, state))
+    }
+
+    fn state_update(state: StatusEvent, manual_control: bool) -> Option<Message> {
+        let message = StateChangeMessage {
+            event: "state_update".to_string(),
+            state: state.to_string(),
+            manual_control: Some(manual_control),
+        };
+
+        serde_json::to_string(&message)
+            .ok()
+            .map(|msg| Message::Text(msg.into()))
+    }
+
+    async fn handle_websocket(socket: WebSocket, state: AppState) {
+        let (mut sender, mut receiver) = socket.split();
+        let manual_control = state.manual_changes.is_some();
+
+        // Subscribe before reading the current state so no change can slip in between
+        let mut updates = state.tracker.subscribe();
+
+        // Send current state immediately
+        if let Some(current_state) = state.tracker.current() {
+            if let Some(msg) = Self::state_update(current_state, manual_control) {
+                if let Err(e) = sender.send(msg).await {
+                    error!("Failed to send initial state: {}", e);
+                    return;
+                }
+            }
+        }
+
+        // Forward state changes from any source to this client
+        let tracker = state.tracker.clone();
+        let mut send_task = tokio::spawn(async move {
+            loop {
+                let new_state = match updates.recv().await {
+                    Ok(new_state) => new_state,
+                    // Missed some updates, so just resend whatever the state is now
+                    Err(broadcast::error::RecvError::Lagged(_)) => match tracker.current() {
+                        Some(current_state) => current_state,
+                        None => continue,
+                    },
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                if let Some(msg) = Self::state_update(new_state, manual_control) {
+                    if sender.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        // Handle incoming messages from client
+        let mut recv_task = tokio::spawn(async move {
+            while let Some(msg) = receiver.next().await {
+                match msg {
+                    Ok(Message::Text(text)) => {
+                        let text_str = text.to_string();
+                        if let Ok(state_msg) = serde_json::from_str::<StateChangeMessage>(&text_str) {
+                            if state_msg.event == "state_change" {
+                                let new_state = match state_msg.state.as_str() {
+                                    "open" => StatusEvent::Open,
+                                    "closed" => StatusEvent::Closed,
+                                    _ => continue,
+                                };
+
+                                match &state.manual_changes {
+                                    Some(manual_changes) => {
+                                        info!("Manual state change to {} from web page", new_state);
+                                        if manual_changes.send(new_state).is_err() {
+                                            error!("Manual state change to {} was not delivered", new_state);
+                                        }
+                                    }
+                                    None => warn!("Ignoring manual state change to {}, manual control is disabled", new_state),
+                                }
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        info!("WebSocket connection closed");
+                        break;
+                    }
+                    Err(e) => {
+                        error!("WebSocket error: {}", e);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        // Whichever side finishes first ends the connection
+        tokio::select! {
+            _ = &mut send_task => recv_task.abort(),
+            _ = &mut recv_task => send_task.abort(),
+        }
+    }
+}